@@ -1,12 +1,30 @@
 use std::fs::File;
 use std::io::{Result, Write};
 
+// The same constants as the kernel, so the assembly can't drift from them
+#[allow(dead_code)]
+mod consts {
+    include!("src/consts.rs");
+}
+
 fn main() {
+    println!("cargo:rerun-if-changed=src/consts.rs");
     println!("cargo:rerun-if-env-changed=USER_IMG");
+    println!("cargo:rerun-if-env-changed=KERNEL_STACK_KB");
     if let Ok(user_img) = std::env::var("USER_IMG") {
         println!("cargo:rerun-if-changed={}", user_img);
     }
     gen_user_asm().unwrap();
+    gen_consts_asm().unwrap();
+}
+
+// Constants of consts.rs used by boot/entry64.asm
+fn gen_consts_asm() -> Result<()> {
+    let mut f = File::create("src/boot/consts.S")?;
+    writeln!(f, "# Generated by build.rs - Do not edit")?;
+    writeln!(f, "    .equ CPU_NUM, {}", consts::CPU_NUM)?;
+    writeln!(f, "    .equ BOOT_STACK_SIZE, {}", consts::BOOT_STACK_SIZE)?;
+    Ok(())
 }
 
 // I think it's not like 'link' but like 'include'
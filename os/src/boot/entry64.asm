@@ -8,6 +8,11 @@ Physical memory status:
     .globl _start
 
 _start:
+    // a0 = hart id, a1 = device tree, both are passed to kernel_entry
+    // Park the harts beyond CPU_NUM, they have no boot stack
+    li t0, CPU_NUM
+    bgeu a0, t0, park
+
     // %hi(addr) for top 20 bits
     // boot_page_table_sv39 is 12 aligned (20 bits + 12 zeros)
     lui t0, %hi(boot_page_table_sv39)
@@ -27,8 +32,14 @@ _start:
     // TLB refresh
     sfence.vma
 
-    // Now set sp as vaddr
+    // Now set sp as vaddr, each hart has its own boot stack (hart 0 on the top)
     lui sp, %hi(boot_stack_top)
+    li t0, BOOT_STACK_SIZE
+    mul t0, t0, a0
+    sub sp, sp, t0
+
+    // The kernel keeps the hart id in tp
+    mv tp, a0
 
     // Below the access will be vaddr mode
     // Jump into kernel entry
@@ -36,12 +47,17 @@ _start:
     addi t0, t0, %lo(kernel_entry)
     jr t0
 
+park:
+    wfi
+    j park
+
     .section .bss.stack
     .align 12
     .global boot_stack
 
 boot_stack:
-    .space 4096 * 4
+    // BOOT_STACK_SIZE for each of the CPU_NUM harts
+    .space BOOT_STACK_SIZE * CPU_NUM
     .global boot_stack_top
 boot_stack_top:
 
@@ -1,9 +1,13 @@
-pub const PHYSICAL_MEMORY_END: usize = 0x88000000;
+// Harts we can run on, build.rs passes it to entry64.asm
+pub const CPU_NUM: usize = 4;
+// Stack of each hart till it runs its idle thread
+pub const BOOT_STACK_SIZE: usize = 4096 * 4;
 
 pub const KERNEL_BEGIN_PADDR: usize = 0x80200000;
 pub const KERNEL_BEGIN_VADDR: usize = 0xffffffffc0200000;
 
-pub const MAX_PHYSICAL_MEMORY: usize = 0x8000000; // 128 MB
+// Memory beyond this (from the start of RAM) is not used
+pub const MAX_PHYSICAL_MEMORY: usize = 0x4000_0000; // 1 GB
 pub const MAX_PHYSICAL_PAGES: usize = MAX_PHYSICAL_MEMORY / PAGE_SIZE;
 
 pub const KERNEL_HEAP_SIZE: usize = 0x800000; // 8 MB
@@ -13,7 +17,28 @@ pub const PHYSICAL_MEMORY_OFFSET: usize = 0xffffffff_40000000;
 // Note that PAGE_SIZE is always (1 << 12) bytes in riscv64
 pub const PAGE_SIZE: usize = 4096;
 
+// Default size of a kernel stack, 'make kernel_stack=<KB>' changes it
 pub const KERNEL_STACK_SIZE: usize = 0x80000; // 512 KB
 
+// Kernel stacks are mapped in 2 MB slots of this region, each above an unmapped guard page
+// The physical window has nothing here (keep in sync with trap.asm)
+pub const KERNEL_STACK_REGION: usize = 0xffffffff_80000000;
+pub const KERNEL_STACK_REGION_SIZE: usize = 0x4000_0000; // 1 GB
+pub const KERNEL_STACK_SLOT: usize = 0x20_0000;
+
 pub const USER_STACK_SIZE: usize = 0x80000;
+// brk grows the heap of a program up to this
+pub const MAX_USER_HEAP_SIZE: usize = 0x1000_0000; // 256 MB
+
+// Most bytes execve takes for the arguments and environment, each pointer counts too
+// (so there are at most ARG_MAX / 8 strings)
+pub const ARG_MAX: usize = 0x20000; // 128 KB
+                                    // Longest path with its NUL
+pub const PATH_MAX: usize = 4096;
+
+// Where mmap looks for free ranges without a hint
+pub const USER_MMAP_BASE: usize = 0x10_0000_0000;
+// User programs map and unmap the lower half of Sv39 only, the upper one is the kernel's
+pub const USER_SPACE_END: usize = 1 << 38;
+// The user stack is the exception, in the upper half below the kernel
 pub const USER_STACK_OFFSET: usize = 0xffffffff00000000;
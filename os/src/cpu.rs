@@ -0,0 +1,9 @@
+// The hart id is kept in tp while running in the kernel (see entry64.asm and trap.asm)
+#[inline(always)]
+pub fn id() -> usize {
+    let id;
+    unsafe {
+        asm!("mv $0, tp" : "=r"(id));
+    }
+    id
+}
@@ -0,0 +1,224 @@
+use super::{Devices, Mmio, MAX_BOOTARGS, MAX_VIRTIO};
+use crate::memory::paddr_to_vaddr;
+use core::{slice, str};
+
+// Flattened device tree, all the numbers are big-endian
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+const MAX_DEPTH: usize = 16;
+const HEADER_SIZE: usize = 40;
+
+// Only the first 1G of physical memory is mapped by the boot page table
+const BOOT_MAPPED: (usize, usize) = (0x8000_0000, 0xc000_0000);
+
+// The readers return None if it's out of the bytes, the tree may be truncated or corrupt
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let mut word = [0u8; 4];
+    word.copy_from_slice(bytes.get(offset..offset.checked_add(4)?)?);
+    Some(u32::from_be_bytes(word))
+}
+
+// A number of 'cells' u32 cells
+fn read_cells(value: &[u8], offset: usize, cells: usize) -> Option<usize> {
+    (0..cells).try_fold(0, |number, i| {
+        Some((number << 32) | be32(value, offset + i * 4)? as usize)
+    })
+}
+
+// Must be terminated in the bytes
+fn cstr(bytes: &[u8], offset: usize) -> Option<&str> {
+    let bytes = bytes.get(offset..)?;
+    let len = bytes.iter().position(|&byte| byte == 0)?;
+    Some(str::from_utf8(&bytes[..len]).unwrap_or(""))
+}
+
+// What we know about the node being walked
+#[derive(Clone, Copy, Default)]
+struct Node {
+    // Cells of 'reg' in the children
+    address_cells: usize,
+    size_cells: usize,
+    base: usize,
+    size: usize,
+    irq: usize,
+    kind: Kind,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Other,
+    Memory,
+    Cpu,
+    Uart,
+    Plic,
+    Clint,
+    Rtc,
+    Virtio,
+    Chosen,
+}
+
+impl Default for Kind {
+    fn default() -> Self {
+        Kind::Other
+    }
+}
+
+fn kind_of(compatible: &str) -> Kind {
+    match compatible {
+        "ns16550a" => Kind::Uart,
+        "riscv,plic0" | "sifive,plic-1.0.0" => Kind::Plic,
+        "riscv,clint0" | "sifive,clint0" => Kind::Clint,
+        "google,goldfish-rtc" => Kind::Rtc,
+        "virtio,mmio" => Kind::Virtio,
+        _ => Kind::Other,
+    }
+}
+
+// Update the devices with the tree at dtb (physical), false if it's not a valid one
+// (the devices are not changed then)
+pub fn parse(dtb: usize, devices: &mut Devices) -> bool {
+    let mut parsed = devices.clone();
+    if walk(dtb, &mut parsed).is_none() {
+        return false;
+    }
+    *devices = parsed;
+    true
+}
+
+fn walk(dtb: usize, devices: &mut Devices) -> Option<()> {
+    if dtb < BOOT_MAPPED.0 || dtb + HEADER_SIZE > BOOT_MAPPED.1 || dtb % 4 != 0 {
+        return None;
+    }
+    let header = unsafe { slice::from_raw_parts(paddr_to_vaddr(dtb) as *const u8, HEADER_SIZE) };
+    if be32(header, 0)? != FDT_MAGIC {
+        return None;
+    }
+    // Nothing is read beyond the total size, and all of it must be mapped
+    let total = be32(header, 4)? as usize;
+    if total < HEADER_SIZE || dtb + total > BOOT_MAPPED.1 {
+        return None;
+    }
+    let fdt = unsafe { slice::from_raw_parts(paddr_to_vaddr(dtb) as *const u8, total) };
+    let structs = fdt.get(be32(header, 8)? as usize..)?;
+    let strings = fdt.get(be32(header, 12)? as usize..)?;
+
+    let mut memory_found = false;
+    let mut harts = 0;
+    let mut virtio_count = 0;
+    // The nodes from the root to the current one, the root uses 2 cells by default
+    let mut stack = [Node::default(); MAX_DEPTH];
+    let mut depth = 0;
+    let mut pos = 0;
+    loop {
+        let token = be32(structs, pos)?;
+        pos += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = cstr(structs, pos)?;
+                pos = (pos + name.len() + 1 + 3) & !3;
+                if depth == MAX_DEPTH {
+                    return None;
+                }
+                let kind = if name == "memory" || name.starts_with("memory@") {
+                    Kind::Memory
+                } else if depth == 2 && name.starts_with("cpu@") {
+                    Kind::Cpu
+                } else if depth == 1 && name == "chosen" {
+                    Kind::Chosen
+                } else {
+                    Kind::Other
+                };
+                stack[depth] = Node {
+                    address_cells: 2,
+                    size_cells: 1,
+                    kind,
+                    ..Node::default()
+                };
+                depth += 1;
+            }
+            FDT_END_NODE => {
+                if depth == 0 {
+                    return None;
+                }
+                depth -= 1;
+                let node = stack[depth];
+                let mmio = Mmio {
+                    base: node.base,
+                    size: node.size,
+                    irq: node.irq,
+                };
+                match node.kind {
+                    Kind::Memory if !memory_found => {
+                        devices.memory = (node.base, node.base.checked_add(node.size)?);
+                        memory_found = true;
+                    }
+                    Kind::Cpu => harts += 1,
+                    Kind::Uart => devices.uart = mmio,
+                    Kind::Plic => devices.plic = mmio,
+                    Kind::Clint => devices.clint = mmio,
+                    Kind::Rtc => devices.rtc = mmio,
+                    Kind::Virtio if virtio_count < MAX_VIRTIO => {
+                        devices.virtio[virtio_count] = mmio;
+                        virtio_count += 1;
+                    }
+                    _ => {}
+                }
+            }
+            FDT_PROP => {
+                let len = be32(structs, pos)? as usize;
+                let name = cstr(strings, be32(structs, pos + 4)? as usize)?;
+                let value = structs.get(pos + 8..pos + 8 + len)?;
+                pos = (pos + 8 + len + 3) & !3;
+                if depth == 0 {
+                    return None;
+                }
+                // 'reg' is in the cells of the parent
+                let (address_cells, size_cells) = if depth >= 2 {
+                    (stack[depth - 2].address_cells, stack[depth - 2].size_cells)
+                } else {
+                    (2, 1)
+                };
+                let node = &mut stack[depth - 1];
+                match name {
+                    "#address-cells" => node.address_cells = be32(value, 0)? as usize,
+                    "#size-cells" => node.size_cells = be32(value, 0)? as usize,
+                    "reg" if len >= (address_cells + size_cells) * 4 => {
+                        node.base = read_cells(value, 0, address_cells)?;
+                        node.size = read_cells(value, address_cells * 4, size_cells)?;
+                    }
+                    "interrupts" if len >= 4 => node.irq = be32(value, 0)? as usize,
+                    "bootargs" if node.kind == Kind::Chosen => {
+                        let bootargs = cstr(value, 0)?.as_bytes();
+                        let len = bootargs.len().min(MAX_BOOTARGS);
+                        devices.bootargs[..len].copy_from_slice(&bootargs[..len]);
+                        devices.bootargs_len = len;
+                    }
+                    "device_type" if cstr(value, 0) == Some("memory") => node.kind = Kind::Memory,
+                    "compatible" if node.kind == Kind::Other => {
+                        // A list of strings, the first known one wins
+                        node.kind = value
+                            .split(|&byte| byte == 0)
+                            .map(|s| kind_of(str::from_utf8(s).unwrap_or("")))
+                            .find(|&kind| kind != Kind::Other)
+                            .unwrap_or(Kind::Other);
+                    }
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => break,
+            _ => return None,
+        }
+    }
+
+    devices.virtio_count = virtio_count;
+    if harts > 0 {
+        devices.harts = harts;
+    }
+    Some(())
+}
@@ -0,0 +1,119 @@
+use crate::consts::CPU_NUM;
+use core::str;
+
+pub mod device_tree;
+pub mod plic;
+pub mod virtio_blk;
+
+pub const MAX_VIRTIO: usize = 8;
+// Longer boot arguments are cut
+pub const MAX_BOOTARGS: usize = 256;
+
+// Registers (physical) and the interrupt number of a device
+#[derive(Clone, Copy, Default)]
+pub struct Mmio {
+    pub base: usize,
+    pub size: usize,
+    pub irq: usize,
+}
+
+// What the machine has, filled from the device tree
+#[derive(Clone)]
+pub struct Devices {
+    // Physical RAM [start, end)
+    pub memory: (usize, usize),
+    pub harts: usize,
+    pub uart: Mmio,
+    pub plic: Mmio,
+    pub clint: Mmio,
+    pub rtc: Mmio,
+    pub virtio: [Mmio; MAX_VIRTIO],
+    pub virtio_count: usize,
+    // 'bootargs' of /chosen, copied since the tree may be in memory we allocate later
+    pub bootargs: [u8; MAX_BOOTARGS],
+    pub bootargs_len: usize,
+}
+
+const fn mmio(base: usize, size: usize, irq: usize) -> Mmio {
+    Mmio { base, size, irq }
+}
+
+// QEMU virt with 128M memory, used if there is no device tree
+static mut DEVICES: Devices = Devices {
+    memory: (0x8000_0000, 0x8800_0000),
+    harts: CPU_NUM,
+    uart: mmio(0x1000_0000, 0x100, 10),
+    plic: mmio(0x0c00_0000, 0x60_0000, 0),
+    clint: mmio(0x0200_0000, 0x1_0000, 0),
+    rtc: mmio(0x0010_1000, 0x1000, 11),
+    virtio: [
+        mmio(0x1000_1000, 0x1000, 1),
+        mmio(0x1000_2000, 0x1000, 2),
+        mmio(0x1000_3000, 0x1000, 3),
+        mmio(0x1000_4000, 0x1000, 4),
+        mmio(0x1000_5000, 0x1000, 5),
+        mmio(0x1000_6000, 0x1000, 6),
+        mmio(0x1000_7000, 0x1000, 7),
+        mmio(0x1000_8000, 0x1000, 8),
+    ],
+    virtio_count: MAX_VIRTIO,
+    bootargs: [0; MAX_BOOTARGS],
+    bootargs_len: 0,
+};
+
+// Called by the boot hart before anything else, dtb is the physical address from SBI
+pub fn initialize(dtb: usize) {
+    let devices = unsafe { &mut DEVICES };
+    if device_tree::parse(dtb, devices) {
+        println!("[kernel] Device tree at {:#x}:", dtb);
+    } else {
+        println!("[kernel] No device tree at {:#x}, assuming QEMU virt:", dtb);
+    }
+    println!(
+        "    memory [{:#x}, {:#x}) ({} MB), {} harts",
+        devices.memory.0,
+        devices.memory.1,
+        (devices.memory.1 - devices.memory.0) >> 20,
+        devices.harts
+    );
+    for (name, device) in [
+        ("uart", devices.uart),
+        ("plic", devices.plic),
+        ("clint", devices.clint),
+        ("rtc", devices.rtc),
+    ]
+    .iter()
+    {
+        println!("    {:6} {:#x} irq {}", name, device.base, device.irq);
+    }
+    for device in devices.virtio() {
+        println!("    {:6} {:#x} irq {}", "virtio", device.base, device.irq);
+    }
+    println!("    bootargs '{}'", devices.bootargs());
+}
+
+// Never changed after initialize
+pub fn devices() -> &'static Devices {
+    unsafe { &DEVICES }
+}
+
+impl Devices {
+    pub fn virtio(&self) -> &[Mmio] {
+        &self.virtio[..self.virtio_count]
+    }
+
+    pub fn bootargs(&self) -> &str {
+        str::from_utf8(&self.bootargs[..self.bootargs_len]).unwrap_or("")
+    }
+
+    // The value of 'key=value' in the boot arguments
+    pub fn bootarg(&self, key: &str) -> Option<&str> {
+        self.bootargs().split_whitespace().find_map(|arg| {
+            let mut parts = arg.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) if name == key => Some(value),
+                _ => None,
+            }
+        })
+    }
+}
@@ -0,0 +1,91 @@
+use crate::consts::CPU_NUM;
+use crate::cpu;
+use crate::drivers::devices;
+use crate::memory::paddr_to_vaddr;
+use crate::sync::spin_no_irq::SpinNoIrqLock;
+use alloc::{collections::BTreeMap, sync::Arc};
+use lazy_static::*;
+
+// Registers of the PLIC, a context is (hart, privilege mode)
+const PRIORITY: usize = 0x0;
+const ENABLE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const THRESHOLD: usize = 0x0;
+const CLAIM: usize = 0x4;
+
+// The pages we use: priorities, enables and the S-mode contexts of our harts
+pub const MAPPED: [(usize, usize); 2] = [
+    (PRIORITY, ENABLE + 0x1000),
+    (CONTEXT, CONTEXT + CONTEXT_STRIDE * 2 * CPU_NUM),
+];
+
+pub trait IrqHandler: Send + Sync {
+    fn handle_irq(&self);
+}
+
+impl<F: Fn() + Send + Sync> IrqHandler for F {
+    fn handle_irq(&self) {
+        self()
+    }
+}
+
+lazy_static! {
+    static ref HANDLERS: SpinNoIrqLock<BTreeMap<usize, Arc<dyn IrqHandler>>> =
+        SpinNoIrqLock::new(BTreeMap::new());
+}
+
+// External interrupts only go to the boot hart, set by initialize
+static mut TARGET: usize = 0;
+
+// Context of the S-mode of a hart on QEMU virt (the M-mode one is before it)
+fn context(hart: usize) -> usize {
+    hart * 2 + 1
+}
+
+fn reg(offset: usize) -> *mut u32 {
+    paddr_to_vaddr(devices().plic.base + offset) as *mut u32
+}
+
+fn context_reg(context: usize, offset: usize) -> *mut u32 {
+    reg(CONTEXT + CONTEXT_STRIDE * context + offset)
+}
+
+// Let the current hart take the external interrupts, nothing is enabled yet
+pub fn initialize() {
+    unsafe {
+        TARGET = context(cpu::id());
+        context_reg(TARGET, THRESHOLD).write_volatile(0);
+    }
+}
+
+// Enable the irq with priority 1 (above the threshold), the handler runs with interrupts disabled
+pub fn register(irq: usize, handler: Arc<dyn IrqHandler>) {
+    HANDLERS.lock().insert(irq, handler);
+    unsafe {
+        reg(PRIORITY + irq * 4).write_volatile(1);
+        let enable = reg(ENABLE + ENABLE_STRIDE * TARGET + irq / 32 * 4);
+        enable.write_volatile(enable.read_volatile() | 1 << (irq % 32));
+    }
+}
+
+// Claim the pending ones till none is left, every claim is completed after the handler
+pub fn handle_external() {
+    let claim = context_reg(unsafe { TARGET }, CLAIM);
+    loop {
+        let irq = unsafe { claim.read_volatile() } as usize;
+        if irq == 0 {
+            break;
+        }
+        // The handler may take other locks, don't hold the table
+        let handler = HANDLERS.lock().get(&irq).cloned();
+        match handler {
+            Some(handler) => handler.handle_irq(),
+            None => println!("[kernel] Unhandled external interrupt {}", irq),
+        }
+        unsafe {
+            claim.write_volatile(irq as u32);
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use crate::consts::PAGE_SIZE;
+use crate::drivers::devices;
+use crate::memory::{frame_alloc_contiguous, paddr_to_vaddr};
+use crate::sync::mutex::Mutex;
+use core::mem::size_of;
+use core::sync::atomic::{fence, Ordering};
+use rcore_fs::dev::{BlockDevice, DevError, Result};
+
+// Registers of virtio-mmio (legacy ones are only used by version 1)
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const DEVICE_FEATURES_SEL: usize = 0x014;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+const GUEST_PAGE_SIZE: usize = 0x028;
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+const QUEUE_ALIGN: usize = 0x03c;
+const QUEUE_PFN: usize = 0x040;
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DESC_HIGH: usize = 0x084;
+const QUEUE_DRIVER_LOW: usize = 0x090;
+const QUEUE_DRIVER_HIGH: usize = 0x094;
+const QUEUE_DEVICE_LOW: usize = 0x0a0;
+const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+// Capacity in sectors (u64)
+const CONFIG_CAPACITY: usize = 0x100;
+
+const MAGIC: u32 = 0x7472_6976; // "virt"
+const DEVICE_BLOCK: u32 = 2;
+
+// Bits of the status register
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+
+// Bit 32 (bit 0 of the second word), accepted for version 2 devices, we use no other features
+const FEATURE_VERSION_1: u32 = 1;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_OK: u8 = 0;
+
+const QUEUE_SIZE: usize = 16;
+const SECTOR_SIZE: usize = 512;
+// One SFS block is transferred by a request
+const BLOCK_SIZE_LOG2: u8 = 12;
+const BLOCK_SIZE: usize = 1 << BLOCK_SIZE_LOG2;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct AvailRing {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+    used_event: u16,
+}
+
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+#[repr(C)]
+struct UsedRing {
+    flags: u16,
+    idx: u16,
+    ring: [UsedElem; QUEUE_SIZE],
+    avail_event: u16,
+}
+
+#[repr(C)]
+struct Request {
+    type_: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+#[repr(C, align(4096))]
+struct PageAligned<T>(T);
+
+// Shared with the device, the rings are in the legacy layout (used ring at the next page)
+// The data is copied through here, the buffers from rcore-fs may not be physically contiguous
+#[repr(C, align(4096))]
+struct DmaArea {
+    desc: [Descriptor; QUEUE_SIZE],
+    avail: AvailRing,
+    request: Request,
+    status: u8,
+    used: PageAligned<UsedRing>,
+    data: PageAligned<[u8; BLOCK_SIZE]>,
+}
+
+struct Inner {
+    dma: &'static mut DmaArea,
+    paddr: usize,
+    // Where the device will put the next used one
+    used_idx: u16,
+}
+
+// A virtio block device, one request is on the fly at a time and we poll for it
+// The others sleep on the lock meanwhile, and the poller may be preempted
+pub struct VirtIOBlk {
+    inner: Mutex<Inner>,
+    base: usize,
+    capacity: usize,
+    pub irq: usize,
+}
+
+fn read_reg(base: usize, offset: usize) -> u32 {
+    unsafe { ((base + offset) as *const u32).read_volatile() }
+}
+
+fn write_reg(base: usize, offset: usize, value: u32) {
+    unsafe { ((base + offset) as *mut u32).write_volatile(value) }
+}
+
+// Find the first block device in the virtio-mmio slots
+pub fn probe() -> Option<VirtIOBlk> {
+    devices()
+        .virtio()
+        .iter()
+        .map(|device| (paddr_to_vaddr(device.base), device.irq))
+        .find(|&(base, _)| {
+            read_reg(base, MAGIC_VALUE) == MAGIC && read_reg(base, DEVICE_ID) == DEVICE_BLOCK
+        })
+        .and_then(|(base, irq)| VirtIOBlk::new(base, irq))
+}
+
+impl VirtIOBlk {
+    // Base is the virtual address of the registers
+    pub fn new(base: usize, irq: usize) -> Option<Self> {
+        let version = read_reg(base, VERSION);
+
+        // Reset, then tell it that we know how to drive it
+        write_reg(base, STATUS, 0);
+        let mut status = STATUS_ACKNOWLEDGE | STATUS_DRIVER;
+        write_reg(base, STATUS, status);
+        write_reg(base, DEVICE_FEATURES_SEL, 0);
+        write_reg(base, DRIVER_FEATURES_SEL, 0);
+        write_reg(base, DRIVER_FEATURES, 0);
+        if version >= 2 {
+            write_reg(base, DRIVER_FEATURES_SEL, 1);
+            write_reg(base, DRIVER_FEATURES, FEATURE_VERSION_1);
+            status |= STATUS_FEATURES_OK;
+            write_reg(base, STATUS, status);
+            if read_reg(base, STATUS) & STATUS_FEATURES_OK == 0 {
+                println!("[kernel] virtio-blk at {:#x} rejected the features.", base);
+                return None;
+            }
+        }
+
+        write_reg(base, QUEUE_SEL, 0);
+        if (read_reg(base, QUEUE_NUM_MAX) as usize) < QUEUE_SIZE {
+            println!("[kernel] virtio-blk at {:#x} has a too small queue.", base);
+            return None;
+        }
+        let pages = size_of::<DmaArea>() / PAGE_SIZE;
+        let paddr = frame_alloc_contiguous(pages, 1)?.start_address().as_usize();
+        let area = paddr_to_vaddr(paddr) as *mut DmaArea;
+        let dma = unsafe {
+            (area as *mut u8).write_bytes(0, size_of::<DmaArea>());
+            &mut *area
+        };
+        write_reg(base, QUEUE_NUM, QUEUE_SIZE as u32);
+        if version == 1 {
+            write_reg(base, GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+            write_reg(base, QUEUE_ALIGN, PAGE_SIZE as u32);
+            write_reg(base, QUEUE_PFN, (paddr / PAGE_SIZE) as u32);
+        } else {
+            let to_paddr = |field: usize| (field - area as usize + paddr) as u64;
+            let desc = to_paddr(&dma.desc as *const _ as usize);
+            let avail = to_paddr(&dma.avail as *const _ as usize);
+            let used = to_paddr(&dma.used as *const _ as usize);
+            write_reg(base, QUEUE_DESC_LOW, desc as u32);
+            write_reg(base, QUEUE_DESC_HIGH, (desc >> 32) as u32);
+            write_reg(base, QUEUE_DRIVER_LOW, avail as u32);
+            write_reg(base, QUEUE_DRIVER_HIGH, (avail >> 32) as u32);
+            write_reg(base, QUEUE_DEVICE_LOW, used as u32);
+            write_reg(base, QUEUE_DEVICE_HIGH, (used >> 32) as u32);
+            write_reg(base, QUEUE_READY, 1);
+        }
+        write_reg(base, STATUS, status | STATUS_DRIVER_OK);
+
+        let capacity = read_reg(base, CONFIG_CAPACITY) as usize
+            | (read_reg(base, CONFIG_CAPACITY + 4) as usize) << 32;
+        println!(
+            "[kernel] Found virtio-blk at {:#x} with {} sectors.",
+            base, capacity
+        );
+        Some(VirtIOBlk {
+            inner: Mutex::new(Inner {
+                dma,
+                paddr,
+                used_idx: 0,
+            }),
+            base,
+            capacity,
+            irq,
+        })
+    }
+
+    // The completion is polled, the interrupt only has to be cleared
+    pub fn handle_irq(&self) {
+        write_reg(
+            self.base,
+            INTERRUPT_ACK,
+            read_reg(self.base, INTERRUPT_STATUS),
+        );
+    }
+
+    // Transfer one block with the data area, a chain of header -> data -> status
+    fn request(
+        &self,
+        block_id: usize,
+        write: bool,
+        f: impl FnOnce(&mut [u8; BLOCK_SIZE]),
+    ) -> Result<()> {
+        let sectors = BLOCK_SIZE / SECTOR_SIZE;
+        if (block_id + 1) * sectors > self.capacity {
+            return Err(DevError);
+        }
+        let mut inner = self.inner.lock();
+        let Inner {
+            dma,
+            paddr,
+            used_idx,
+        } = &mut *inner;
+        let start = &**dma as *const DmaArea as usize;
+        let to_paddr = |field: usize| (field - start + *paddr) as u64;
+
+        if write {
+            f(&mut dma.data.0);
+        }
+        dma.request = Request {
+            type_: if write { REQUEST_OUT } else { REQUEST_IN },
+            reserved: 0,
+            sector: (block_id * sectors) as u64,
+        };
+        dma.status = 0xff;
+        dma.desc[0] = Descriptor {
+            addr: to_paddr(&dma.request as *const _ as usize),
+            len: size_of::<Request>() as u32,
+            flags: DESC_F_NEXT,
+            next: 1,
+        };
+        dma.desc[1] = Descriptor {
+            addr: to_paddr(&dma.data as *const _ as usize),
+            len: BLOCK_SIZE as u32,
+            flags: DESC_F_NEXT | if write { 0 } else { DESC_F_WRITE },
+            next: 2,
+        };
+        dma.desc[2] = Descriptor {
+            addr: to_paddr(&dma.status as *const _ as usize),
+            len: 1,
+            flags: DESC_F_WRITE,
+            next: 0,
+        };
+        let idx = dma.avail.idx;
+        dma.avail.ring[idx as usize % QUEUE_SIZE] = 0;
+        // The device must see the descriptors before the new index
+        fence(Ordering::SeqCst);
+        dma.avail.idx = idx.wrapping_add(1);
+        fence(Ordering::SeqCst);
+        write_reg(self.base, QUEUE_NOTIFY, 0);
+
+        *used_idx = used_idx.wrapping_add(1);
+        while unsafe { (&dma.used.0.idx as *const u16).read_volatile() } != *used_idx {}
+        fence(Ordering::SeqCst);
+
+        if unsafe { (&dma.status as *const u8).read_volatile() } != REQUEST_OK {
+            return Err(DevError);
+        }
+        if !write {
+            f(&mut dma.data.0);
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for VirtIOBlk {
+    const BLOCK_SIZE_LOG2: u8 = BLOCK_SIZE_LOG2;
+
+    fn read_at(&self, block_id: usize, buf: &mut [u8]) -> Result<()> {
+        self.request(block_id, false, |data| {
+            buf.copy_from_slice(&data[..buf.len()])
+        })
+    }
+
+    fn write_at(&self, block_id: usize, buf: &[u8]) -> Result<()> {
+        self.request(block_id, true, |data| {
+            data[..buf.len()].copy_from_slice(buf)
+        })
+    }
+
+    // Writes are done when the request is used, nothing is cached
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
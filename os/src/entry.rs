@@ -1,24 +1,45 @@
 use crate::consts::*;
+use crate::drivers::devices;
+use core::sync::atomic::{AtomicBool, Ordering};
 
-global_asm!(include_str!("boot/entry64.asm"));
+// CPU_NUM and BOOT_STACK_SIZE come from consts.rs through build.rs
+global_asm!(concat!(
+    include_str!("boot/consts.S"),
+    include_str!("boot/entry64.asm")
+));
 
 // User program code
 global_asm!(include_str!("link_user.S"));
 
+// The first hart coming in initializes the kernel, the others wait till it's done
+static BOOT_HART_CHOSEN: AtomicBool = AtomicBool::new(false);
+static OTHERS_CAN_START: AtomicBool = AtomicBool::new(false);
+
 #[no_mangle]
-pub extern "C" fn kernel_entry() -> ! {
+pub extern "C" fn kernel_entry(hart_id: usize, dtb: usize) -> ! {
+    if BOOT_HART_CHOSEN.swap(true, Ordering::AcqRel) {
+        other_main(hart_id);
+    }
+
     extern "C" {
         fn end();
     }
     println!("[kernel] rCore-OS Kernel");
 
+    // Find out the memory and the devices
+    crate::drivers::initialize(dtb);
+
     let kernel_end_paddr = end as usize - KERNEL_BEGIN_VADDR + KERNEL_BEGIN_PADDR;
+    let (memory_start, memory_end) = devices().memory;
+    // The physical window reaches the top of the address space, without its last page
+    // the end of the memory has a virtual address too
+    let window_end = 0usize.wrapping_sub(PHYSICAL_MEMORY_OFFSET) - PAGE_SIZE;
+    let memory_end = memory_end
+        .min(memory_start.saturating_add(MAX_PHYSICAL_MEMORY))
+        .min(window_end);
 
     // Memory initialization (initialize using physical page range)
-    crate::memory::initialize(
-        (kernel_end_paddr / PAGE_SIZE) + 1,
-        PHYSICAL_MEMORY_END / PAGE_SIZE,
-    );
+    crate::memory::initialize((kernel_end_paddr / PAGE_SIZE) + 1, memory_end / PAGE_SIZE);
 
     // Interrupt initialization
     crate::interrupt::initialize();
@@ -29,8 +50,39 @@ pub extern "C" fn kernel_entry() -> ! {
     // Timer initialization
     crate::timer::initialize();
 
+    // Other harts
+    OTHERS_CAN_START.store(true, Ordering::Release);
+    start_others(hart_id);
+
     // Start threads
     crate::process::run();
 
     loop {}
 }
+
+// Ask SBI to start the other harts from _start (the physical address)
+// It fails for the harts not existing or already running (old SBI starts all of them)
+fn start_others(boot_hart: usize) {
+    extern "C" {
+        fn _start();
+    }
+    let entry = _start as usize - KERNEL_BEGIN_VADDR + KERNEL_BEGIN_PADDR;
+    let harts = devices().harts.min(CPU_NUM);
+    for hart_id in (0..harts).filter(|&id| id != boot_hart) {
+        crate::sbi::hart_start(hart_id, entry, 0);
+    }
+}
+
+fn other_main(hart_id: usize) -> ! {
+    while !OTHERS_CAN_START.load(Ordering::Acquire) {}
+
+    crate::memory::initialize_other();
+    crate::interrupt::initialize_other();
+    crate::process::initialize_other();
+    crate::timer::initialize_other();
+    println!("[kernel] Hart {} started.", hart_id);
+
+    crate::process::run();
+
+    loop {}
+}
@@ -0,0 +1,47 @@
+use rcore_fs::vfs::FsError;
+
+// Linux error numbers, syscalls return the negative ones
+#[repr(isize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Errno {
+    EPERM = 1,
+    ENOENT = 2,
+    EIO = 5,
+    E2BIG = 7,
+    ENOEXEC = 8,
+    EBADF = 9,
+    ECHILD = 10,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EFAULT = 14,
+    EEXIST = 17,
+    ENOTDIR = 20,
+    EISDIR = 21,
+    EINVAL = 22,
+    EMFILE = 24,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    EPIPE = 32,
+    ENAMETOOLONG = 36,
+    ENOSYS = 38,
+    ENOTEMPTY = 39,
+}
+
+impl From<FsError> for Errno {
+    fn from(error: FsError) -> Self {
+        match error {
+            FsError::NotSupported => Errno::ENOSYS,
+            FsError::NotFile => Errno::EISDIR,
+            FsError::IsDir => Errno::EISDIR,
+            FsError::NotDir => Errno::ENOTDIR,
+            FsError::EntryNotFound => Errno::ENOENT,
+            FsError::EntryExist => Errno::EEXIST,
+            FsError::InvalidParam => Errno::EINVAL,
+            FsError::NoDeviceSpace => Errno::ENOSPC,
+            FsError::DirNotEmpty => Errno::ENOTEMPTY,
+            // Only returned by pipes, as pipe::BROKEN_PIPE
+            FsError::NotSameFs => Errno::EPIPE,
+            _ => Errno::EIO,
+        }
+    }
+}
@@ -1,9 +1,11 @@
+use crate::sync::rwlock::RwLock;
+use alloc::string::String;
+use core::fmt::Write;
 use core::slice::from_raw_parts_mut;
 use rcore_fs::dev::*;
-use spin::RwLock;
 
 // Use a read-write lock
-// Threads can read at a same time, but only one can write
+// Threads can read at a same time, but only one can write (the others sleep)
 pub struct MemDisk(RwLock<&'static mut [u8]>);
 
 impl MemDisk {
@@ -32,7 +34,27 @@ impl Device for MemDisk {
         Ok(len)
     }
 
+    // Nowhere to write back, so dump the image over the console for the host ('make undump')
+    // Lines are in the format of xxd, the ones with only zeros are skipped
     fn sync(&self) -> Result<()> {
+        let slice = self.0.read();
+        println!("[usr.img begin]");
+        for (i, line) in slice.chunks(16).enumerate() {
+            let last = (i + 1) * 16 >= slice.len();
+            if !last && line.iter().all(|&byte| byte == 0) {
+                continue;
+            }
+            let mut text = String::with_capacity(48);
+            write!(text, "{:08x}:", i * 16).unwrap();
+            for pair in line.chunks(2) {
+                text.push(' ');
+                for byte in pair {
+                    write!(text, "{:02x}", byte).unwrap();
+                }
+            }
+            println!("{}", text);
+        }
+        println!("[usr.img end]");
         Ok(())
     }
 }
@@ -0,0 +1,156 @@
+use crate::fs::stdio::{STDIN, STDOUT};
+use crate::sync::mutex::Mutex;
+use alloc::{sync::Arc, vec::Vec};
+use rcore_fs::vfs::*;
+
+// Descriptors are below this
+pub const MAX_FDS: usize = 1024;
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+// An opened file (shared by the descriptors duplicated from the same one)
+// The offset is held through a whole read or write, so the holders never use the same range
+pub struct File {
+    inode: Arc<dyn INode>,
+    offset: Mutex<usize>,
+    readable: bool,
+    writable: bool,
+}
+
+impl File {
+    pub fn new(inode: Arc<dyn INode>, readable: bool, writable: bool) -> Self {
+        File {
+            inode,
+            offset: Mutex::new(0),
+            readable,
+            writable,
+        }
+    }
+
+    pub fn inode(&self) -> Arc<dyn INode> {
+        self.inode.clone()
+    }
+
+    // The lock sleeps, so stdin and pipes may sleep inside with it held
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if !self.readable {
+            return Err(FsError::InvalidParam);
+        }
+        let mut offset = self.offset.lock();
+        let len = self.inode.read_at(*offset, buf)?;
+        *offset += len;
+        Ok(len)
+    }
+
+    // Read at the offset, the offset of the file is not changed
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if !self.readable {
+            return Err(FsError::InvalidParam);
+        }
+        self.inode.read_at(offset, buf)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize> {
+        if !self.writable {
+            return Err(FsError::InvalidParam);
+        }
+        let mut offset = self.offset.lock();
+        let len = self.inode.write_at(*offset, buf)?;
+        *offset += len;
+        Ok(len)
+    }
+
+    // Return the new offset
+    pub fn seek(&self, offset: isize, whence: usize) -> Result<usize> {
+        let mut current = self.offset.lock();
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => *current as isize,
+            SEEK_END => self.inode.metadata()?.size as isize,
+            _ => return Err(FsError::InvalidParam),
+        };
+        if base + offset < 0 {
+            return Err(FsError::InvalidParam);
+        }
+        *current = (base + offset) as usize;
+        Ok(*current)
+    }
+}
+
+// A slot of the table, cloexec for closing the file on exec
+#[derive(Clone)]
+struct Descriptor {
+    file: Arc<File>,
+    cloexec: bool,
+}
+
+// File descriptor table of a process, shared by all its threads
+#[derive(Clone)]
+pub struct FdTable {
+    files: Vec<Option<Descriptor>>,
+}
+
+impl FdTable {
+    // With stdin (0), stdout (1) and stderr (2) opened
+    pub fn new() -> Self {
+        let stdin: Arc<dyn INode> = STDIN.clone();
+        let stdout: Arc<dyn INode> = STDOUT.clone();
+        let mut table = FdTable { files: Vec::new() };
+        table.add(Arc::new(File::new(stdin, true, false)), false);
+        table.add(Arc::new(File::new(stdout.clone(), false, true)), false);
+        table.add(Arc::new(File::new(stdout, false, true)), false);
+        table
+    }
+
+    // Use the lowest free descriptor, None if all the MAX_FDS are used
+    pub fn add(&mut self, file: Arc<File>, cloexec: bool) -> Option<usize> {
+        let descriptor = Some(Descriptor { file, cloexec });
+        for (fd, slot) in self.files.iter_mut().enumerate() {
+            if slot.is_none() {
+                *slot = descriptor;
+                return Some(fd);
+            }
+        }
+        if self.files.len() == MAX_FDS {
+            return None;
+        }
+        self.files.push(descriptor);
+        Some(self.files.len() - 1)
+    }
+
+    pub fn get(&self, fd: usize) -> Option<Arc<File>> {
+        match self.files.get(fd) {
+            Some(Some(descriptor)) => Some(descriptor.file.clone()),
+            _ => None,
+        }
+    }
+
+    // Put the file at the given descriptor (below MAX_FDS), return the one closed
+    pub fn set(&mut self, fd: usize, file: Arc<File>, cloexec: bool) -> Option<Arc<File>> {
+        assert!(fd < MAX_FDS);
+        if fd >= self.files.len() {
+            self.files.resize(fd + 1, None);
+        }
+        self.files[fd]
+            .replace(Descriptor { file, cloexec })
+            .map(|descriptor| descriptor.file)
+    }
+
+    pub fn remove(&mut self, fd: usize) -> Option<Arc<File>> {
+        self.files
+            .get_mut(fd)
+            .and_then(|slot| slot.take())
+            .map(|descriptor| descriptor.file)
+    }
+
+    // Take out the files to be closed by exec
+    pub fn close_on_exec(&mut self) -> Vec<Arc<File>> {
+        self.files
+            .iter_mut()
+            .filter(|slot| slot.as_ref().map_or(false, |descriptor| descriptor.cloexec))
+            .map(|slot| slot.take().unwrap().file)
+            .collect()
+    }
+}
@@ -1,23 +1,36 @@
+use crate::drivers::{plic, virtio_blk};
 use alloc::{sync::Arc, vec::Vec};
 use lazy_static::*;
+use rcore_fs::dev::Device;
 use rcore_fs::vfs::*;
 use rcore_fs_sfs::SimpleFileSystem;
 
 pub mod device;
+pub mod file;
+pub mod pipe;
 pub mod stdio;
 
 // What is a 'lazy_static'?
 // Initialize when used (runtime) but not at compile
 lazy_static! {
     pub static ref ROOT_INODE: Arc<dyn INode> = {
-        let device = {
-            extern "C" {
-                fn _user_img_start();
-                fn _user_img_end();
-            };
-            let start = _user_img_start as usize;
-            let end = _user_img_end as usize;
-            Arc::new(unsafe { device::MemDisk::new(start, end) })
+        // Use the virtio disk if QEMU gives us one, or the image linked into the kernel
+        let device: Arc<dyn Device> = match virtio_blk::probe() {
+            Some(blk) => {
+                let blk = Arc::new(blk);
+                let handler = blk.clone();
+                plic::register(blk.irq, Arc::new(move || handler.handle_irq()));
+                blk
+            }
+            None => {
+                extern "C" {
+                    fn _user_img_start();
+                    fn _user_img_end();
+                };
+                let start = _user_img_start as usize;
+                let end = _user_img_end as usize;
+                Arc::new(unsafe { device::MemDisk::new(start, end) })
+            }
         };
         let sfs = SimpleFileSystem::open(device).unwrap();
         sfs.root_inode()
@@ -0,0 +1,138 @@
+use crate::sync::condvar::Condvar;
+use crate::sync::spin_no_irq::SpinNoIrqLock;
+use alloc::sync::Arc;
+use core::any::Any;
+use rcore_fs::vfs::*;
+
+const PIPE_SIZE: usize = 4096;
+
+// Writing with the read end closed, rcore-fs has no such error so it takes one
+// the kernel never returns otherwise (there are no links), it becomes EPIPE
+pub const BROKEN_PIPE: FsError = FsError::NotSameFs;
+
+// A ring buffer, 'head' is where the next byte is read
+struct Buffer {
+    data: [u8; PIPE_SIZE],
+    head: usize,
+    len: usize,
+    reader_closed: bool,
+    writer_closed: bool,
+}
+
+struct Pipe {
+    buf: SpinNoIrqLock<Buffer>,
+    readable: Condvar,
+    writable: Condvar,
+}
+
+// One end of a pipe, the end is closed when the last file using it is dropped
+pub struct PipeEnd {
+    pipe: Arc<Pipe>,
+    is_reader: bool,
+}
+
+// Return the read end and the write end
+pub fn new_pipe() -> (Arc<PipeEnd>, Arc<PipeEnd>) {
+    let pipe = Arc::new(Pipe {
+        buf: SpinNoIrqLock::new(Buffer {
+            data: [0; PIPE_SIZE],
+            head: 0,
+            len: 0,
+            reader_closed: false,
+            writer_closed: false,
+        }),
+        readable: Condvar::new(),
+        writable: Condvar::new(),
+    });
+    let reader = PipeEnd {
+        pipe: pipe.clone(),
+        is_reader: true,
+    };
+    let writer = PipeEnd {
+        pipe,
+        is_reader: false,
+    };
+    (Arc::new(reader), Arc::new(writer))
+}
+
+impl INode for PipeEnd {
+    // Wait for some bytes, Ok(0) for EOF once the writer is closed
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if !self.is_reader {
+            return Err(FsError::NotSupported);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut pipe = self.pipe.buf.lock();
+        while pipe.len == 0 {
+            if pipe.writer_closed {
+                return Ok(0);
+            }
+            pipe = self.pipe.readable.wait_spin(pipe);
+        }
+        let len = buf.len().min(pipe.len);
+        for byte in buf[..len].iter_mut() {
+            *byte = pipe.data[pipe.head];
+            pipe.head = (pipe.head + 1) % PIPE_SIZE;
+        }
+        pipe.len -= len;
+        drop(pipe);
+        self.pipe.writable.notify_all();
+        Ok(len)
+    }
+
+    // Block till all the bytes are written, fail if nobody would read them
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        if self.is_reader {
+            return Err(FsError::NotSupported);
+        }
+        let mut written = 0;
+        let mut pipe = self.pipe.buf.lock();
+        while written < buf.len() {
+            if pipe.reader_closed {
+                return Err(BROKEN_PIPE);
+            }
+            if pipe.len == PIPE_SIZE {
+                self.pipe.readable.notify_all();
+                pipe = self.pipe.writable.wait_spin(pipe);
+                continue;
+            }
+            let tail = (pipe.head + pipe.len) % PIPE_SIZE;
+            pipe.data[tail] = buf[written];
+            pipe.len += 1;
+            written += 1;
+        }
+        drop(pipe);
+        self.pipe.readable.notify_all();
+        Ok(written)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        let pipe = self.pipe.buf.lock();
+        Ok(PollStatus {
+            read: self.is_reader && (pipe.len > 0 || pipe.writer_closed),
+            write: !self.is_reader && (pipe.len < PIPE_SIZE || pipe.reader_closed),
+            error: false,
+        })
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+// Wake up the other end so it sees EOF (or the broken pipe)
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        let mut pipe = self.pipe.buf.lock();
+        if self.is_reader {
+            pipe.reader_closed = true;
+        } else {
+            pipe.writer_closed = true;
+        }
+        drop(pipe);
+        self.pipe.readable.notify_all();
+        self.pipe.writable.notify_all();
+    }
+}
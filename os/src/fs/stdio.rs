@@ -1,42 +1,110 @@
-use crate::sync::condvar::Condvar;
+use crate::sync::semaphore::Semaphore;
+use crate::sync::spin_no_irq::SpinNoIrqLock;
 use alloc::{collections::VecDeque, sync::Arc};
+use core::any::Any;
 use lazy_static::*;
-use spin::Mutex;
+use rcore_fs::vfs::*;
 
+// Pushed by the interrupt handler, so the buffer is behind a spin lock
+// The semaphore counts the chars in it
 pub struct Stdin {
-    buf: Mutex<VecDeque<char>>,
-    pushed: Condvar,
+    buf: SpinNoIrqLock<VecDeque<char>>,
+    pushed: Semaphore,
 }
 
 impl Stdin {
     pub fn new() -> Self {
         Stdin {
-            buf: Mutex::new(VecDeque::new()),
-            pushed: Condvar::new(),
+            buf: SpinNoIrqLock::new(VecDeque::new()),
+            pushed: Semaphore::new(0),
         }
     }
 
     pub fn push(&self, ch: char) {
         self.buf.lock().push_back(ch);
-        self.pushed.notify(); // To wake up some threads
+        self.pushed.up(); // To wake up some threads
     }
 
     // Get a char (or to say 'wait')
     pub fn pop(&self) -> char {
-        loop {
-            let ret = self.buf.lock().pop_front();
-            match ret {
-                Some(ch) => {
-                    return ch;
-                }
-                None => {
-                    self.pushed.wait();
-                }
+        self.pushed.down();
+        self.buf.lock().pop_front().unwrap()
+    }
+
+    // Get a char if there is one
+    pub fn try_pop(&self) -> Option<char> {
+        if self.pushed.try_down() {
+            self.buf.lock().pop_front()
+        } else {
+            None
+        }
+    }
+}
+
+impl INode for Stdin {
+    // Wait for the first char, then take the others already there
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        buf[0] = self.pop() as u8;
+        let mut len = 1;
+        while len < buf.len() {
+            match self.try_pop() {
+                Some(ch) => buf[len] = ch as u8,
+                None => break,
             }
+            len += 1;
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: !self.buf.lock().is_empty(),
+            write: false,
+            error: false,
+        })
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+// Console output, the offset makes no sense
+pub struct Stdout;
+
+impl INode for Stdout {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        for ch in buf {
+            crate::io::putchar(*ch as char);
         }
+        Ok(buf.len())
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: false,
+            write: true,
+            error: false,
+        })
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
     }
 }
 
 lazy_static! {
     pub static ref STDIN: Arc<Stdin> = Arc::new(Stdin::new());
+    pub static ref STDOUT: Arc<Stdout> = Arc::new(Stdout);
 }
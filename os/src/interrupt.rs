@@ -3,14 +3,35 @@ use riscv::register::{
     sie, sscratch, sstatus, stvec,
 };
 
+use crate::drivers::{devices, plic};
 use crate::memory::paddr_to_vaddr;
-use crate::process::tick;
-use crate::timer::set_next_event;
+use crate::process::{current_tid, exit, handle_page_fault, stack, tick, ExitCode};
+use crate::timer::{set_next_event, wake_expired};
 use crate::trap::frame::TrapFrame;
+use alloc::sync::Arc;
 
 global_asm!(include_str!("trap/trap.asm"));
 
+// Exit codes (128 + signal number, like a shell reports) for the threads killed by the kernel
+const EXIT_SIGILL: ExitCode = 128 + 4;
+const EXIT_SIGSEGV: ExitCode = 128 + 11;
+
 pub fn initialize() {
+    initialize_other();
+    // External interrupts go to this hart
+    plic::initialize();
+    plic::register(devices().uart.irq, Arc::new(access_serial));
+    unsafe {
+        sie::set_sext();
+
+        // Disabled by OpenSBI, open serial interrupt manually
+        enable_serial_interrupt();
+    }
+    println!("[kernel] Interrupt initialized.");
+}
+
+// The trap entry of every hart, external interrupts only go to the boot hart
+pub fn initialize_other() {
     unsafe {
         extern "C" {
             fn __trap_entry();
@@ -20,30 +41,21 @@ pub fn initialize() {
         // Direct mode: jump to ebase directly when trapped
         stvec::write(__trap_entry as usize, stvec::TrapMode::Direct);
         sstatus::set_sie();
-
-        sie::set_sext();
-
-        // Disabled by OpenSBI, open external interrupt and serial manually
-        init_external_interrupt();
-        enable_serial_interrupt();
     }
-    println!("[kernel] Interrupt initialized.");
-}
-
-pub unsafe fn init_external_interrupt() {
-    const SERIAL: u32 = 0xa;
-    let hart0_s_mode_interrupt_enables: *mut u32 = paddr_to_vaddr(0x0c00_2080) as *mut u32;
-    hart0_s_mode_interrupt_enables.write_volatile(1 << SERIAL);
 }
 
 pub unsafe fn enable_serial_interrupt() {
-    let uart16550: *mut u8 = paddr_to_vaddr(0x10000000) as *mut u8;
+    let uart16550: *mut u8 = paddr_to_vaddr(devices().uart.base) as *mut u8;
     uart16550.add(4).write_volatile(0x0B);
     uart16550.add(1).write_volatile(0x01);
 }
 
 #[no_mangle]
 fn trap_handler(frame: &mut TrapFrame) {
+    // trap.asm moves to the overflow stack when a kernel stack has no room for the trap frame
+    if on_overflow_stack(frame) {
+        stack_overflow(frame);
+    }
     match frame.scause.cause() {
         Trap::Exception(Exception::Breakpoint) => breakpoint_handler(&mut frame.sepc),
         Trap::Interrupt(Interrupt::SupervisorTimer) => supervisor_timer_handler(),
@@ -51,35 +63,37 @@ fn trap_handler(frame: &mut TrapFrame) {
         Trap::Exception(Exception::LoadPageFault) => page_fault(frame),
         Trap::Exception(Exception::StorePageFault) => page_fault(frame),
         Trap::Exception(Exception::UserEnvCall) => syscall(frame),
-        Trap::Interrupt(Interrupt::SupervisorExternal) => external_handler(),
-        Trap::Exception(Exception::IllegalInstruction) => panic!("Illegal instruction."), // For lab-1
+        Trap::Interrupt(Interrupt::SupervisorExternal) => plic::handle_external(),
+        Trap::Exception(Exception::IllegalInstruction) => illegal_instruction(frame),
         _ => panic!("Undefined trap."),
     }
 }
 
-fn external_handler() {
-    access_serial();
-}
-
+// Take all the chars received
 fn access_serial() {
-    match super::io::getchar() {
-        Some(ch) => {
-            crate::fs::stdio::STDIN.push({
-                if ch == '\r' {
-                    '\n'
-                } else {
-                    ch
-                }
-            });
-        }
-        None => {}
+    while let Some(ch) = super::io::getchar() {
+        crate::fs::stdio::STDIN.push({
+            if ch == '\r' {
+                '\n'
+            } else {
+                ch
+            }
+        });
     }
 }
 
 fn syscall(frame: &mut TrapFrame) {
     // Return address (skip ecall)
     frame.sepc += 4;
-    let ret = crate::syscall::syscall(frame.x[17], [frame.x[10], frame.x[11], frame.x[12]]);
+    let args = [
+        frame.x[10],
+        frame.x[11],
+        frame.x[12],
+        frame.x[13],
+        frame.x[14],
+        frame.x[15],
+    ];
+    let ret = crate::syscall::syscall(frame.x[17], args, frame);
     frame.x[10] = ret as usize;
 }
 
@@ -89,6 +103,16 @@ fn breakpoint_handler(sepc: &mut usize) {
 }
 
 fn page_fault(frame: &mut TrapFrame) {
+    if !from_user(frame) && stack::is_guard(frame.stval) {
+        stack_overflow(frame);
+    }
+    // Lazy allocation or copy-on-write
+    if handle_page_fault(frame.stval) {
+        return;
+    }
+    if from_user(frame) {
+        kill(frame, EXIT_SIGSEGV);
+    }
     println!(
         "{:?} vaddr = {:#x} instruction = {:#x}",
         frame.scause.cause(),
@@ -98,8 +122,57 @@ fn page_fault(frame: &mut TrapFrame) {
     panic!("Page fault");
 }
 
+fn on_overflow_stack(frame: &TrapFrame) -> bool {
+    extern "C" {
+        fn overflow_stack();
+        fn overflow_stack_top();
+    }
+    let addr = frame as *const TrapFrame as usize;
+    addr >= overflow_stack as usize && addr < overflow_stack_top as usize
+}
+
+// The kernel code using the stack may hold any lock, the thread can't be killed
+fn stack_overflow(frame: &TrapFrame) -> ! {
+    panic!(
+        "Kernel stack overflow in thread {}: sp = {:#x}, stval = {:#x}, sepc = {:#x}",
+        current_tid(),
+        frame.x[2],
+        frame.stval,
+        frame.sepc
+    );
+}
+
+fn illegal_instruction(frame: &mut TrapFrame) {
+    if from_user(frame) {
+        kill(frame, EXIT_SIGILL);
+    }
+    panic!("Illegal instruction at {:#x}", frame.sepc);
+}
+
+// SPP records the privilege level before the trap
+fn from_user(frame: &TrapFrame) -> bool {
+    match frame.sstatus.spp() {
+        sstatus::SPP::User => true,
+        sstatus::SPP::Supervisor => false,
+    }
+}
+
+// Only the faulting thread dies, the kernel keeps going
+fn kill(frame: &TrapFrame, code: ExitCode) -> ! {
+    println!(
+        "[kernel] Thread {} killed by {:?}: sepc = {:#x}, stval = {:#x}, exit code = {}",
+        current_tid(),
+        frame.scause.cause(),
+        frame.sepc,
+        frame.stval,
+        code
+    );
+    exit(code)
+}
+
 fn supervisor_timer_handler() {
     set_next_event();
+    wake_expired();
     tick();
 }
 
@@ -109,10 +182,10 @@ fn supervisor_timer_handler() {
 pub fn disable_and_store() -> usize {
     let sstatus: usize;
     unsafe {
-        // Disable all the async interrupt and return the old one
-        asm!("csrci sstatus, 1 << 1" : "=r"(sstatus) ::: "volatile");
+        // Disable all the async interrupt and return the old one (only SIE is kept)
+        asm!("csrrci $0, sstatus, 1 << 1" : "=r"(sstatus) ::: "volatile");
     }
-    sstatus
+    sstatus & (1 << 1)
 }
 
 #[inline(always)]
@@ -1,4 +1,5 @@
 use crate::sbi;
+use crate::sync::spin_no_irq::SpinNoIrqLock;
 use core::fmt::{self, Write};
 
 struct StdOut;
@@ -10,7 +11,11 @@ impl fmt::Write for StdOut {
     }
 }
 
+// Keep the output of different harts from interleaving
+static PRINT_LOCK: SpinNoIrqLock<()> = SpinNoIrqLock::new(());
+
 pub fn _print(args: fmt::Arguments) {
+    let _lock = PRINT_LOCK.lock();
     StdOut.write_fmt(args).unwrap();
 }
 
@@ -1,23 +1,47 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(test, allow(dead_code))]
 #![feature(asm)]
 #![feature(global_asm)]
 #![feature(alloc_error_handler)]
 #![feature(naked_functions)]
 
+// The host tests ('make test') only build what doesn't need the riscv machine
+#[cfg(not(test))]
 #[macro_use]
 mod io;
 
 mod consts;
+#[cfg(not(test))]
+mod cpu;
+#[cfg(not(test))]
+mod drivers;
+#[cfg(not(test))]
 mod entry;
+#[cfg(not(test))]
+mod errno;
+#[cfg(not(test))]
 mod fs;
+#[cfg(not(test))]
 mod interrupt;
+#[cfg(not(test))]
 mod lang;
+#[cfg(not(test))]
 mod memory;
+#[cfg(not(test))]
 mod process;
+#[cfg(not(test))]
 mod sbi;
+#[cfg(not(test))]
 mod sync;
+#[cfg(not(test))]
 mod syscall;
+#[cfg(not(test))]
 mod timer;
+#[cfg(not(test))]
 mod trap;
 
+#[cfg(test)]
+#[path = "memory/frame_allocator.rs"]
+mod frame_allocator;
+
 extern crate alloc;
@@ -1,32 +1,253 @@
 use crate::consts::MAX_PHYSICAL_PAGES;
+use core::mem::size_of;
 
-// Physical frame allocator
-pub struct LinearFrameAllocator {
-    pub flags: [bool; MAX_PHYSICAL_PAGES],
-    pub offset: usize,
-    pub size: usize,
+// Physical frame allocator, all the numbers are physical page numbers
+pub trait FrameAllocator {
+    // Frames in [l, r) can be allocated
+    fn initialize(&mut self, l: usize, r: usize);
+
+    fn alloc(&mut self) -> Option<usize>;
+
+    // Continuous frames, the first one is aligned to align (in pages, power of 2)
+    fn alloc_contiguous(&mut self, count: usize, align: usize) -> Option<usize>;
+
+    fn dealloc(&mut self, index: usize);
+
+    // Free the frames returned by alloc_contiguous with the same count and align
+    fn dealloc_contiguous(&mut self, index: usize, count: usize, align: usize);
+
+    fn free_count(&self) -> usize;
+}
+
+const LEAVES: usize = MAX_PHYSICAL_PAGES;
+
+// Buddy system on a complete binary tree (node 1 is the root, leaves are [LEAVES, 2 * LEAVES))
+// Every node records the largest free block in its subtree, so allocation is O(log n)
+pub struct SegmentTreeFrameAllocator {
+    // (Order of the largest free block) + 1, 0 means nothing is free
+    tree: [u8; LEAVES * 2],
+    // The first leaf, aligned to LEAVES so that the blocks are aligned physically
+    base: usize,
+    begin: usize,
+    end: usize,
+    free: usize,
 }
 
-impl LinearFrameAllocator {
-    pub fn initialize(&mut self, l: usize, r: usize) {
-        self.offset = l;
-        self.size = r - l;
+impl SegmentTreeFrameAllocator {
+    pub const fn new() -> Self {
+        SegmentTreeFrameAllocator {
+            tree: [0; LEAVES * 2],
+            base: 0,
+            begin: 0,
+            end: 0,
+            free: 0,
+        }
+    }
+
+    // Order of the block covered by the node
+    fn order(node: usize) -> usize {
+        let depth = size_of::<usize>() * 8 - 1 - node.leading_zeros() as usize;
+        LEAVES.trailing_zeros() as usize - depth
     }
 
-    pub fn alloc(&mut self) -> usize {
-        for index in 0..self.size {
-            if !self.flags[index] {
-                self.flags[index] = true;
-                return index + self.offset;
-            }
+    // Merge two free buddies or take the larger one
+    fn update(&mut self, node: usize) {
+        let full = Self::order(node) as u8;
+        let (left, right) = (self.tree[node * 2], self.tree[node * 2 + 1]);
+        self.tree[node] = if left == full && right == full {
+            full + 1
+        } else {
+            left.max(right)
+        };
+    }
+
+    // Blocks are powers of 2, large enough for count and aligned to their size
+    fn block_order(count: usize, align: usize) -> usize {
+        count.max(align).max(1).next_power_of_two().trailing_zeros() as usize
+    }
+
+    fn alloc_order(&mut self, order: usize) -> Option<usize> {
+        let value = order as u8 + 1;
+        if self.tree[1] < value {
+            return None;
+        }
+
+        // Always go left if possible
+        let mut node = 1;
+        while Self::order(node) > order {
+            node = if self.tree[node * 2] >= value {
+                node * 2
+            } else {
+                node * 2 + 1
+            };
         }
-        panic!("Physical memory depleted");
+        self.tree[node] = 0;
+        self.free -= 1 << order;
+
+        let index = (node << order) - LEAVES;
+        while node > 1 {
+            node /= 2;
+            self.update(node);
+        }
+        Some(index + self.base)
+    }
+}
+
+impl FrameAllocator for SegmentTreeFrameAllocator {
+    fn initialize(&mut self, l: usize, r: usize) {
+        assert!(LEAVES.is_power_of_two());
+        self.base = l & !(LEAVES - 1);
+        self.begin = l;
+        self.end = r.min(self.base + LEAVES);
+        if self.end < r {
+            println!(
+                "[kernel] Frames [{:#x}, {:#x}) are beyond the allocator, not used.",
+                self.end, r
+            );
+        }
+        self.free = self.end - self.begin;
+        for index in 0..LEAVES {
+            let ppn = self.base + index;
+            self.tree[LEAVES + index] = if ppn >= self.begin && ppn < self.end {
+                1
+            } else {
+                0
+            };
+        }
+        for node in (1..LEAVES).rev() {
+            self.update(node);
+        }
+    }
+
+    fn alloc(&mut self) -> Option<usize> {
+        self.alloc_order(0)
+    }
+
+    // The block size is rounded up to a power of 2
+    fn alloc_contiguous(&mut self, count: usize, align: usize) -> Option<usize> {
+        self.alloc_order(Self::block_order(count, align))
+    }
+
+    fn dealloc(&mut self, index: usize) {
+        self.dealloc_contiguous(index, 1, 1);
+    }
+
+    // The allocated block is the lowest ancestor marked full
+    fn dealloc_contiguous(&mut self, index: usize, count: usize, align: usize) {
+        assert!(index >= self.begin && index < self.end);
+        let index = index - self.base;
+        let mut node = LEAVES + index;
+        while self.tree[node] != 0 {
+            assert!(node > 1, "Freeing a free frame");
+            node /= 2;
+        }
+        let order = Self::order(node);
+        assert_eq!(index % (1 << order), 0, "Not the start of a block");
+        assert_eq!(
+            order,
+            Self::block_order(count, align),
+            "Not the size of the block"
+        );
+        self.tree[node] = order as u8 + 1;
+        self.free += 1 << order;
+
+        while node > 1 {
+            node /= 2;
+            self.update(node);
+        }
+    }
+
+    fn free_count(&self) -> usize {
+        self.free
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Aligned to LEAVES like the start of RAM (0x8000_0000)
+    const BASE: usize = 0x80000;
+
+    fn allocator(l: usize, r: usize) -> Box<SegmentTreeFrameAllocator> {
+        let mut allocator = Box::new(SegmentTreeFrameAllocator::new());
+        allocator.initialize(BASE + l, BASE + r);
+        allocator
+    }
+
+    #[test]
+    fn split_and_merge() {
+        let mut allocator = allocator(0, 1024);
+        assert_eq!(allocator.free_count(), 1024);
+        // The single frame splits the only 1024 block
+        let frame = allocator.alloc().unwrap();
+        assert_eq!(frame, BASE);
+        assert_eq!(allocator.free_count(), 1023);
+        assert_eq!(allocator.alloc_contiguous(1024, 1), None);
+        assert_eq!(allocator.alloc_contiguous(512, 1), Some(BASE + 512));
+        // Merged back with its buddies
+        allocator.dealloc(frame);
+        allocator.dealloc_contiguous(BASE + 512, 512, 1);
+        assert_eq!(allocator.free_count(), 1024);
+        assert_eq!(allocator.alloc_contiguous(1024, 1), Some(BASE));
+        assert_eq!(allocator.free_count(), 0);
+        assert_eq!(allocator.alloc(), None);
+    }
+
+    #[test]
+    fn fragmented() {
+        let mut allocator = allocator(0, 16);
+        let frames: Vec<usize> = (0..4).map(|_| allocator.alloc().unwrap()).collect();
+        assert_eq!(frames, [BASE, BASE + 1, BASE + 2, BASE + 3]);
+        // Two free frames, but not buddies
+        allocator.dealloc(BASE);
+        allocator.dealloc(BASE + 2);
+        assert_eq!(allocator.free_count(), 14);
+        assert_eq!(allocator.alloc_contiguous(2, 1), Some(BASE + 4));
+        allocator.dealloc(BASE + 1);
+        assert_eq!(allocator.alloc_contiguous(2, 1), Some(BASE));
+        // The single frames go to the holes first
+        assert_eq!(allocator.alloc(), Some(BASE + 2));
+        assert_eq!(allocator.free_count(), 10);
+    }
+
+    #[test]
+    fn aligned() {
+        // The first frame can't be used, so no block of 8 from it
+        let mut allocator = allocator(1, 64);
+        assert_eq!(allocator.free_count(), 63);
+        assert_eq!(allocator.alloc_contiguous(1, 8), Some(BASE + 8));
+        assert_eq!(allocator.free_count(), 55);
+        // 3 frames take a block of 4
+        assert_eq!(allocator.alloc_contiguous(3, 1), Some(BASE + 4));
+        assert_eq!(allocator.free_count(), 51);
+        assert_eq!(allocator.alloc_contiguous(16, 16), Some(BASE + 16));
+        allocator.dealloc_contiguous(BASE + 8, 1, 8);
+        allocator.dealloc_contiguous(BASE + 4, 3, 1);
+        allocator.dealloc_contiguous(BASE + 16, 16, 16);
+        assert_eq!(allocator.free_count(), 63);
+    }
+
+    #[test]
+    fn beyond_leaves() {
+        let allocator = allocator(0, LEAVES + 16);
+        assert_eq!(allocator.free_count(), LEAVES);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not the size of the block")]
+    fn wrong_count() {
+        let mut allocator = allocator(0, 16);
+        let frame = allocator.alloc_contiguous(4, 1).unwrap();
+        allocator.dealloc_contiguous(frame, 2, 1);
     }
 
-    pub fn dealloc(&mut self, index: usize) {
-        let index = index - self.offset;
-        assert!(index < self.size);
-        assert!(self.flags[index]);
-        self.flags[index] = false;
+    #[test]
+    #[should_panic(expected = "Freeing a free frame")]
+    fn double_free() {
+        let mut allocator = allocator(0, 16);
+        let frame = allocator.alloc().unwrap();
+        allocator.dealloc(frame);
+        allocator.dealloc(frame);
     }
 }
@@ -3,8 +3,12 @@ use crate::memory::manager::attr::MemoryAttr;
 use crate::memory::manager::handler::Handler;
 use crate::memory::manager::paging::range::VirtualPageRange;
 use crate::memory::manager::paging::table::PageTable;
+use crate::memory::{frame_dealloc, frame_is_shared};
 use alloc::boxed::Box;
+use alloc::vec::Vec;
+use riscv::addr::{Frame, PhysAddr};
 
+#[derive(Clone)]
 pub struct Area {
     start: usize,
     end: usize,
@@ -23,18 +27,94 @@ impl Area {
         }
     }
 
-    pub fn map(&self, page_table: &mut PageTable) {
+    // False if some page has no frame, the pages mapped before are unmapped then
+    pub fn map(&self, page_table: &mut PageTable) -> bool {
         for page in VirtualPageRange::new(self.start, self.end) {
-            self.handler.map(page_table, page, &self.attr);
+            if !self.handler.map(page_table, page, &self.attr) {
+                // Nobody has used them, no TLB to flush
+                for frame in self.slice(self.start, page).unmap(page_table) {
+                    frame_dealloc(frame);
+                }
+                return false;
+            }
         }
+        true
+    }
+
+    // The frames are left to the caller, to be freed after the TLBs are flushed
+    pub fn unmap(&self, page_table: &mut PageTable) -> Vec<Frame> {
+        VirtualPageRange::new(self.start, self.end)
+            .filter_map(|page| self.handler.unmap(page_table, page))
+            .collect()
     }
 
-    pub fn unmap(&self, page_table: &mut PageTable) {
+    // Share the pages of this area with another page table
+    pub fn clone_map(&self, src: &mut PageTable, dst: &mut PageTable) {
         for page in VirtualPageRange::new(self.start, self.end) {
-            self.handler.unmap(page_table, page);
+            self.handler.clone_map(src, dst, page, &self.attr);
         }
     }
 
+    pub fn handle_page_fault(&self, page_table: &mut PageTable, vaddr: usize) -> bool {
+        self.handler
+            .handle_page_fault(page_table, vaddr, &self.attr)
+    }
+
+    pub fn attr(&self) -> &MemoryAttr {
+        &self.attr
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    // A part of this area with the same handler and attributes (nothing is mapped or unmapped)
+    pub fn slice(&self, start: usize, end: usize) -> Area {
+        Area::new(start, end, self.handler.clone(), self.attr.clone())
+    }
+
+    // Move the end (page aligned), map the new pages or unmap the ones cut off
+    // Return the frames of the pages cut off like unmap
+    pub fn resize(&mut self, page_table: &mut PageTable, end: usize) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        if end > self.end {
+            self.slice(self.end, end).map(page_table);
+        } else if end < self.end {
+            frames = self.slice(end, self.end).unmap(page_table);
+        }
+        self.end = end;
+        frames
+    }
+
+    // Rewrite the entries with new attributes, lazy pages stay invalid
+    // and copy-on-write ones stay read-only till they are written
+    pub fn set_attr(&mut self, page_table: &mut PageTable, attr: MemoryAttr) {
+        for page in VirtualPageRange::new(self.start, self.end) {
+            if let Some(entry) = page_table.get_entry(page) {
+                let present = entry.present();
+                let shared =
+                    present && frame_is_shared(Frame::of_addr(PhysAddr::new(entry.target())));
+                attr.apply(entry);
+                if !present {
+                    entry.set_present(false);
+                }
+                if shared {
+                    entry.set_writable(false);
+                }
+                entry.update();
+            }
+        }
+        self.attr = attr;
+    }
+
+    pub fn contains(&self, vaddr: usize) -> bool {
+        self.is_overlap_with(vaddr, vaddr + 1)
+    }
+
     // The area is page size times, check whether overlapped with others
     pub fn is_overlap_with(&self, start: usize, end: usize) -> bool {
         let p1 = self.start / PAGE_SIZE;
@@ -1,6 +1,7 @@
 use crate::memory::manager::paging::entry::PageEntry;
 
 // Attribution for a memory area
+#[derive(Clone)]
 pub struct MemoryAttr {
     user: bool,
     read_only: bool,
@@ -31,6 +32,14 @@ impl MemoryAttr {
         self
     }
 
+    pub fn user(&self) -> bool {
+        self.user
+    }
+
+    pub fn writable(&self) -> bool {
+        !self.read_only
+    }
+
     pub fn apply(&self, entry: &mut PageEntry) {
         // Present means readable and valid
         entry.set_present(true);
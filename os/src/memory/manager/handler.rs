@@ -1,8 +1,12 @@
 use crate::consts::PAGE_SIZE;
 use crate::memory::manager::attr::MemoryAttr;
 use crate::memory::manager::paging::table::PageTable;
-use crate::memory::{frame_alloc, paddr_to_vaddr};
+use crate::memory::{
+    flush_harts, frame_alloc, frame_dealloc, frame_is_shared, frame_share, frame_unshare,
+    paddr_to_vaddr,
+};
 use alloc::boxed::Box;
+use riscv::addr::{Frame, PhysAddr};
 
 // Memory handler is more likely a wrapper (for virtual pages in memory area, setting up the mapping by different ways).
 // The handler must ensure no overlapping
@@ -10,14 +14,29 @@ pub trait Handler: 'static {
     // Grammar 'dyn' is used to solve the ambiguity, MemoryHandler is a structure or trait (yes) ?
     fn box_clone(&self) -> Box<dyn Handler>;
 
-    fn unmap(&self, page_table: &mut PageTable, vaddr: usize) {
+    // Return the frame to free, only after no TLB has the page any more
+    fn unmap(&self, page_table: &mut PageTable, vaddr: usize) -> Option<Frame> {
         page_table.unmap(vaddr);
+        None
     }
 
-    // The only difference between handlers
-    fn map(&self, page_table: &mut PageTable, vaddr: usize, attr: &MemoryAttr);
+    // The only difference between handlers, false if there is no frame for the page
+    fn map(&self, page_table: &mut PageTable, vaddr: usize, attr: &MemoryAttr) -> bool;
 
     fn page_copy(&self, page_table: &mut PageTable, vaddr: usize, src: usize, length: usize);
+
+    // Map the page into another page table as well (used by fork)
+    fn clone_map(&self, src: &mut PageTable, dst: &mut PageTable, vaddr: usize, attr: &MemoryAttr);
+
+    // Try to fix the fault at vaddr, false means it's a real access violation
+    fn handle_page_fault(
+        &self,
+        _page_table: &mut PageTable,
+        _vaddr: usize,
+        _attr: &MemoryAttr,
+    ) -> bool {
+        false
+    }
 }
 
 impl Clone for Box<dyn Handler> {
@@ -43,8 +62,9 @@ impl Handler for Linear {
         Box::new(self.clone())
     }
 
-    fn map(&self, page_table: &mut PageTable, vaddr: usize, attr: &MemoryAttr) {
+    fn map(&self, page_table: &mut PageTable, vaddr: usize, attr: &MemoryAttr) -> bool {
         attr.apply(page_table.map(vaddr, vaddr - self.offset));
+        true
     }
 
     fn page_copy(&self, _page_table: &mut PageTable, vaddr: usize, src: usize, length: usize) {
@@ -61,6 +81,17 @@ impl Handler for Linear {
             }
         }
     }
+
+    // Kernel areas are the same in every address space
+    fn clone_map(
+        &self,
+        _src: &mut PageTable,
+        dst: &mut PageTable,
+        vaddr: usize,
+        attr: &MemoryAttr,
+    ) {
+        self.map(dst, vaddr, attr);
+    }
 }
 
 // Allocate new frame for area mapping
@@ -78,10 +109,26 @@ impl Handler for ByFrame {
         Box::new(self.clone())
     }
 
-    fn map(&self, page_table: &mut PageTable, vaddr: usize, attr: &MemoryAttr) {
-        let frame = frame_alloc().unwrap();
+    fn map(&self, page_table: &mut PageTable, vaddr: usize, attr: &MemoryAttr) -> bool {
+        let frame = match frame_alloc() {
+            Some(frame) => frame,
+            None => return false,
+        };
         let paddr = frame.start_address().as_usize();
         attr.apply(page_table.map(vaddr, paddr));
+        true
+    }
+
+    // The frame is freed by the last page table using it
+    fn unmap(&self, page_table: &mut PageTable, vaddr: usize) -> Option<Frame> {
+        let paddr = page_table.get_entry(vaddr).unwrap().target();
+        page_table.unmap(vaddr);
+        let frame = Frame::of_addr(PhysAddr::new(paddr));
+        if frame_unshare(frame.clone()) {
+            None
+        } else {
+            Some(frame)
+        }
     }
 
     // TODO: why it's different there?
@@ -100,4 +147,155 @@ impl Handler for ByFrame {
             }
         }
     }
+
+    // Both sides share the frame read-only, the first write will copy it
+    fn clone_map(&self, src: &mut PageTable, dst: &mut PageTable, vaddr: usize, attr: &MemoryAttr) {
+        let entry = src.get_entry(vaddr).unwrap();
+        let paddr = entry.target();
+        if entry.writable() {
+            entry.set_writable(false);
+            entry.update();
+        }
+        frame_share(Frame::of_addr(PhysAddr::new(paddr)));
+
+        let entry = dst.map(vaddr, paddr);
+        attr.apply(entry);
+        entry.set_writable(false);
+    }
+
+    // Copy-on-write
+    fn handle_page_fault(
+        &self,
+        page_table: &mut PageTable,
+        vaddr: usize,
+        attr: &MemoryAttr,
+    ) -> bool {
+        let entry = match page_table.get_entry(vaddr) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        if !entry.present() || entry.writable() || !attr.writable() {
+            return false;
+        }
+
+        let paddr = entry.target();
+        let frame = Frame::of_addr(PhysAddr::new(paddr));
+        let mut unused = None;
+        if frame_is_shared(frame.clone()) {
+            // Others are using the frame, make a private copy
+            // Without a frame for it the share is kept and the fault kills the process
+            let target = match frame_alloc() {
+                Some(copy) => copy.start_address().as_usize(),
+                None => return false,
+            };
+            unsafe {
+                let src =
+                    core::slice::from_raw_parts(paddr_to_vaddr(paddr) as *const u8, PAGE_SIZE);
+                let dst =
+                    core::slice::from_raw_parts_mut(paddr_to_vaddr(target) as *mut u8, PAGE_SIZE);
+                dst.copy_from_slice(src);
+            }
+            entry.set_target(target);
+            // The others may have dropped it meanwhile
+            if !frame_unshare(frame.clone()) {
+                unused = Some(frame);
+            }
+        }
+        entry.set_writable(true);
+        entry.update();
+        // Other harts running the address space may have the old entry
+        flush_harts(vaddr, vaddr + PAGE_SIZE);
+        if let Some(frame) = unused {
+            frame_dealloc(frame);
+        }
+        true
+    }
+}
+
+// Allocate the frame only when the page is accessed for the first time (demand paging)
+#[derive(Clone)]
+pub struct ByFrameLazy;
+
+impl ByFrameLazy {
+    pub fn new() -> Self {
+        ByFrameLazy {}
+    }
+
+    // Back the invalid entry with a zeroed frame, false if there is no frame
+    fn alloc_frame(&self, page_table: &mut PageTable, vaddr: usize) -> bool {
+        let frame = match frame_alloc() {
+            Some(frame) => frame,
+            None => return false,
+        };
+        let paddr = frame.start_address().as_usize();
+        unsafe {
+            let dst = core::slice::from_raw_parts_mut(paddr_to_vaddr(paddr) as *mut u8, PAGE_SIZE);
+            for i in 0..PAGE_SIZE {
+                dst[i] = 0;
+            }
+        }
+        let entry = page_table.get_entry(vaddr).unwrap();
+        entry.set_target(paddr);
+        entry.set_present(true);
+        entry.update();
+        true
+    }
+}
+
+impl Handler for ByFrameLazy {
+    fn box_clone(&self) -> Box<dyn Handler> {
+        Box::new(self.clone())
+    }
+
+    // Only an invalid entry with the attributes, no frame yet
+    fn map(&self, page_table: &mut PageTable, vaddr: usize, attr: &MemoryAttr) -> bool {
+        let entry = page_table.map(vaddr, 0);
+        attr.apply(entry);
+        entry.set_present(false);
+        true
+    }
+
+    fn unmap(&self, page_table: &mut PageTable, vaddr: usize) -> Option<Frame> {
+        if page_table.get_entry(vaddr).unwrap().present() {
+            ByFrame::new().unmap(page_table, vaddr)
+        } else {
+            page_table.unmap(vaddr);
+            None
+        }
+    }
+
+    // Data must be there, so no laziness here
+    fn page_copy(&self, page_table: &mut PageTable, vaddr: usize, src: usize, length: usize) {
+        let allocated = self.alloc_frame(page_table, vaddr);
+        assert!(allocated, "No frame for the data");
+        ByFrame::new().page_copy(page_table, vaddr, src, length);
+    }
+
+    // Pages never touched stay lazy in both
+    fn clone_map(&self, src: &mut PageTable, dst: &mut PageTable, vaddr: usize, attr: &MemoryAttr) {
+        if src.get_entry(vaddr).unwrap().present() {
+            ByFrame::new().clone_map(src, dst, vaddr, attr);
+        } else {
+            self.map(dst, vaddr, attr);
+        }
+    }
+
+    fn handle_page_fault(
+        &self,
+        page_table: &mut PageTable,
+        vaddr: usize,
+        attr: &MemoryAttr,
+    ) -> bool {
+        let present = match page_table.get_entry(vaddr) {
+            Some(entry) => entry.present(),
+            None => return false,
+        };
+        if present {
+            // Maybe a copy-on-write one
+            ByFrame::new().handle_page_fault(page_table, vaddr, attr)
+        } else {
+            // Out of memory kills the process
+            self.alloc_frame(page_table, vaddr)
+        }
+    }
 }
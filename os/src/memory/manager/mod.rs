@@ -1,34 +1,45 @@
-use crate::consts::{PAGE_SIZE, PHYSICAL_MEMORY_END, PHYSICAL_MEMORY_OFFSET};
+use crate::consts::{PAGE_SIZE, PHYSICAL_MEMORY_OFFSET};
 use crate::memory::manager::area::Area;
 use crate::memory::manager::attr::MemoryAttr;
-use crate::memory::manager::handler::{Handler, Linear};
+use crate::memory::manager::handler::{ByFrameLazy, Handler, Linear};
+use crate::memory::manager::paging::range::VirtualPageRange;
 use crate::memory::manager::paging::table::PageTable;
-use crate::memory::paddr_to_vaddr;
+use crate::memory::{flush_harts, frame_dealloc, map_devices, paddr_to_vaddr, physical_memory_end};
 use alloc::boxed::Box;
 use alloc::vec::Vec;
+use riscv::addr::Frame;
+use riscv::paging::PageTableFlags as EF;
 
 pub mod area;
 pub mod attr;
 pub mod handler;
 pub mod paging;
 
+const MEGAPAGE_SIZE: usize = 0x20_0000;
+
 pub struct Manager {
     areas: Vec<Area>,
     page_table: PageTable,
 }
 
 impl Manager {
+    // The kernel space is shared with the kernel page table, only user areas are here
     pub fn new() -> Self {
-        let mut memory_set = Manager {
+        Manager {
             areas: Vec::new(),
             page_table: PageTable::new(),
-        };
+        }
+    }
+
+    // Built once at boot, before any other Manager
+    pub fn new_kernel() -> Self {
+        let mut memory_set = Manager::new();
         memory_set.initialize();
         memory_set
     }
 
     // Map kernel and physical memory
-    pub fn initialize(&mut self) {
+    fn initialize(&mut self) {
         extern "C" {
             fn stext();
             fn etext();
@@ -75,16 +86,43 @@ impl Manager {
             None,
         );
         // Physical memory RW
-        self.push(
+        self.map_physical_memory(
             (end as usize / PAGE_SIZE + 1) * PAGE_SIZE,
-            paddr_to_vaddr(PHYSICAL_MEMORY_END),
-            MemoryAttr::new(),
-            Linear::new(offset),
-            None,
+            paddr_to_vaddr(physical_memory_end()),
         );
+        map_devices(self);
     }
 
-    // Push a new area
+    // 2 MB pages in the middle, 4 KB pages for the parts not 2 MB aligned
+    fn map_physical_memory(&mut self, start: usize, end: usize) {
+        let mega_start = ((start + MEGAPAGE_SIZE - 1) / MEGAPAGE_SIZE * MEGAPAGE_SIZE).min(end);
+        let mega_end = (end / MEGAPAGE_SIZE * MEGAPAGE_SIZE).max(mega_start);
+        if start < mega_start {
+            self.push(
+                start,
+                mega_start,
+                MemoryAttr::new(),
+                Linear::new(PHYSICAL_MEMORY_OFFSET),
+                None,
+            );
+        }
+        let flags = EF::VALID | EF::READABLE | EF::WRITABLE | EF::ACCESSED | EF::DIRTY;
+        for vaddr in (mega_start..mega_end).step_by(MEGAPAGE_SIZE) {
+            self.page_table
+                .map_megapage(vaddr, vaddr - PHYSICAL_MEMORY_OFFSET, flags);
+        }
+        if mega_end < end {
+            self.push(
+                mega_end,
+                end,
+                MemoryAttr::new(),
+                Linear::new(PHYSICAL_MEMORY_OFFSET),
+                None,
+            );
+        }
+    }
+
+    // Push a new area, false if there are not enough frames (nothing is pushed then)
     pub fn push(
         &mut self,
         start: usize,
@@ -92,14 +130,130 @@ impl Manager {
         attr: MemoryAttr,
         handler: impl Handler,
         data: Option<(usize, usize)>,
-    ) {
+    ) -> bool {
         let area = Area::new(start, end, Box::new(handler), attr);
-        area.map(&mut self.page_table);
+        if !area.map(&mut self.page_table) {
+            return false;
+        }
         if let Some((src, length)) = data {
             // If there is source address, then copy it
             area.page_copy(&mut self.page_table, src, length);
         }
         self.areas.push(area);
+        true
+    }
+
+    // A new area from start with the frames given mapped in order, they are freed with it
+    pub fn push_frames(&mut self, start: usize, attr: MemoryAttr, frames: Vec<Frame>) {
+        let end = start + frames.len() * PAGE_SIZE;
+        self.push(start, end, attr, ByFrameLazy::new(), None);
+        for (page, frame) in VirtualPageRange::new(start, end).zip(frames) {
+            let entry = self.page_table.get_entry(page).unwrap();
+            entry.set_target(frame.start_address().as_usize());
+            entry.set_present(true);
+            entry.update();
+        }
+    }
+
+    // Duplicate the address space, user pages are shared until someone writes them
+    pub fn fork(&mut self) -> Manager {
+        let mut manager = Manager {
+            areas: Vec::new(),
+            page_table: PageTable::new(),
+        };
+        for area in self.areas.iter() {
+            area.clone_map(&mut self.page_table, &mut manager.page_table);
+            // The pages are read-only now, other harts must not write them through the TLB
+            flush_harts(area.start(), area.end());
+            manager.areas.push(area.clone());
+        }
+        manager
+    }
+
+    // Return false if vaddr is not in any area or the access is not allowed
+    pub fn handle_page_fault(&mut self, vaddr: usize) -> bool {
+        match self.areas.iter().find(|area| area.contains(vaddr)) {
+            Some(area) => area.handle_page_fault(&mut self.page_table, vaddr),
+            None => false,
+        }
+    }
+
+    // Whether user programs can access [start, start + len)
+    pub fn check_user(&self, start: usize, len: usize, write: bool) -> bool {
+        if len == 0 {
+            return true;
+        }
+        let end = match start.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+        VirtualPageRange::new(start, end).all(|page| {
+            self.areas.iter().any(|area| {
+                area.contains(page) && area.attr().user() && (!write || area.attr().writable())
+            })
+        })
+    }
+
+    // Write data into this address space through the physical frames (it may be inactive)
+    // Fail if some page is not in an area or has no frame
+    pub fn write(&mut self, vaddr: usize, data: &[u8]) -> Result<(), &'static str> {
+        let copied = self.for_each_piece(vaddr, data.len(), true, |dst, done, len| unsafe {
+            let dst = core::slice::from_raw_parts_mut(dst as *mut u8, len);
+            dst.copy_from_slice(&data[done..done + len]);
+        });
+        if copied {
+            Ok(())
+        } else {
+            Err("Writing to an invalid address")
+        }
+    }
+
+    // Copy from [vaddr, vaddr + buf.len()) of this address space through the frames,
+    // false if the user can't read it all
+    pub fn read_user(&mut self, vaddr: usize, buf: &mut [u8]) -> bool {
+        if !self.check_user(vaddr, buf.len(), false) {
+            return false;
+        }
+        self.for_each_piece(vaddr, buf.len(), false, |src, done, len| unsafe {
+            let src = core::slice::from_raw_parts(src as *const u8, len);
+            buf[done..done + len].copy_from_slice(src);
+        })
+    }
+
+    // Copy data to vaddr of this address space through the frames, false if the user can't write it all
+    pub fn write_user(&mut self, vaddr: usize, data: &[u8]) -> bool {
+        self.check_user(vaddr, data.len(), true) && self.write(vaddr, data).is_ok()
+    }
+
+    // Call f with the kernel address of each piece of [vaddr, vaddr + len) in a page, the bytes
+    // before it and its length, the pages are made present (and writable to write) first
+    fn for_each_piece(
+        &mut self,
+        vaddr: usize,
+        len: usize,
+        write: bool,
+        mut f: impl FnMut(usize, usize, usize),
+    ) -> bool {
+        let mut done = 0;
+        while done < len {
+            let addr = vaddr + done;
+            let offset = addr % PAGE_SIZE;
+            let piece = (PAGE_SIZE - offset).min(len - done);
+
+            // The page may be lazy or copy-on-write
+            let ready = match self.page_table.get_entry(addr) {
+                Some(entry) => entry.present() && (!write || entry.writable()),
+                None => false,
+            };
+            if !ready && !self.handle_page_fault(addr) {
+                return false;
+            }
+
+            let paddr = self.page_table.get_entry(addr).unwrap().target() + offset;
+            f(paddr_to_vaddr(paddr), done, piece);
+            done += piece;
+        }
+        true
     }
 
     fn test_free_area(&self, start: usize, end: usize) -> bool {
@@ -109,6 +263,108 @@ impl Manager {
             .is_none()
     }
 
+    // The lowest free range of len bytes from hint (page aligned), it must end before limit
+    pub fn find_free_area(&self, hint: usize, len: usize, limit: usize) -> Option<usize> {
+        let mut start = hint;
+        loop {
+            let end = start.checked_add(len)?;
+            if end > limit {
+                return None;
+            }
+            if self.test_free_area(start, end) {
+                return Some(start);
+            }
+            // Skip the areas in the way
+            start = self
+                .areas
+                .iter()
+                .filter(|area| area.is_overlap_with(start, end))
+                .map(|area| (area.end() + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE)
+                .max()
+                .unwrap();
+        }
+    }
+
+    // Unmap [start, end) (page aligned) and free the frames, the parts of the areas outside stay
+    pub fn remove_range(&mut self, start: usize, end: usize) {
+        let areas = core::mem::replace(&mut self.areas, Vec::new());
+        let mut frames = Vec::new();
+        for area in areas {
+            if !area.is_overlap_with(start, end) {
+                self.areas.push(area);
+                continue;
+            }
+            let (inner_start, inner_end) = (area.start().max(start), area.end().min(end));
+            frames.extend(
+                area.slice(inner_start, inner_end)
+                    .unmap(&mut self.page_table),
+            );
+            if area.start() < inner_start {
+                self.areas.push(area.slice(area.start(), inner_start));
+            }
+            if inner_end < area.end() {
+                self.areas.push(area.slice(inner_end, area.end()));
+            }
+        }
+        free_unmapped(start, end, frames);
+    }
+
+    pub fn has_area_at(&self, start: usize) -> bool {
+        self.areas.iter().any(|area| area.start() == start)
+    }
+
+    // Grow or shrink the area starting at start to end (page aligned)
+    // False if there is no such area or the new pages overlap with another one
+    pub fn resize_area(&mut self, start: usize, end: usize) -> bool {
+        let index = match self.areas.iter().position(|area| area.start() == start) {
+            Some(index) => index,
+            None => return false,
+        };
+        let old_end = self.areas[index].end();
+        if end < start || (end > old_end && !self.test_free_area(old_end, end)) {
+            return false;
+        }
+        let frames = self.areas[index].resize(&mut self.page_table, end);
+        if end < old_end {
+            free_unmapped(end, old_end, frames);
+        }
+        true
+    }
+
+    // Change the attributes of [start, end) (page aligned), false if some page is not in an area
+    pub fn protect_range(&mut self, start: usize, end: usize, attr: MemoryAttr) -> bool {
+        let covered = VirtualPageRange::new(start, end)
+            .all(|page| self.areas.iter().any(|area| area.contains(page)));
+        if !covered {
+            return false;
+        }
+        let areas = core::mem::replace(&mut self.areas, Vec::new());
+        for area in areas {
+            if !area.is_overlap_with(start, end) {
+                self.areas.push(area);
+                continue;
+            }
+            let (inner_start, inner_end) = (area.start().max(start), area.end().min(end));
+            let mut inner = area.slice(inner_start, inner_end);
+            inner.set_attr(&mut self.page_table, attr.clone());
+            self.areas.push(inner);
+            if area.start() < inner_start {
+                self.areas.push(area.slice(area.start(), inner_start));
+            }
+            if inner_end < area.end() {
+                self.areas.push(area.slice(inner_end, area.end()));
+            }
+        }
+        // Other harts running this address space may still have the old permissions
+        flush_harts(start, end);
+        true
+    }
+
+    // The kernel space of the page tables created later is this one's
+    pub unsafe fn set_kernel(&self) {
+        self.page_table.set_kernel();
+    }
+
     // Switch to current page table
     pub unsafe fn activate(&self) {
         self.page_table.activate();
@@ -117,4 +373,29 @@ impl Manager {
     pub fn token(&self) -> usize {
         self.page_table.token()
     }
+
+    // Unique while the address space lives, unlike the token whose ASID may change
+    pub fn id(&self) -> usize {
+        self.page_table.root_ppn()
+    }
+}
+
+// Other harts may run the address space, so its pages stay in their TLBs till they are flushed
+fn free_unmapped(start: usize, end: usize, frames: Vec<Frame>) {
+    flush_harts(start, end);
+    for frame in frames {
+        frame_dealloc(frame);
+    }
+}
+
+// Return all the frames of the user areas, table frames are freed by the PageTable
+// Nothing runs on it any more, and its ASID is flushed before it's given out again
+impl Drop for Manager {
+    fn drop(&mut self) {
+        for area in self.areas.iter() {
+            for frame in area.unmap(&mut self.page_table) {
+                frame_dealloc(frame);
+            }
+        }
+    }
 }
@@ -0,0 +1,132 @@
+use crate::consts::CPU_NUM;
+use crate::cpu;
+use crate::sync::spin_no_irq::SpinNoIrqLock;
+use alloc::vec::Vec;
+use lazy_static::*;
+use riscv::register::satp;
+
+// The fields of satp in Sv39: MODE (4 bits), ASID (16 bits), PPN (44 bits)
+const SV39: usize = 8 << 60;
+const ASID_SHIFT: usize = 44;
+const ASID_MASK: usize = 0xffff;
+const PPN_MASK: usize = (1 << ASID_SHIFT) - 1;
+
+// More ASIDs only make the owner table bigger
+const MAX_ASID_COUNT: usize = 4096;
+
+// ASID 0 is the kernel's, the others are given out in order
+// When all are used a new generation starts: the owners are forgotten and
+// the address spaces get new ones the next time they are switched to
+struct Allocator {
+    // The root frame (ppn) of the page table using each ASID in this generation
+    owners: Vec<usize>,
+    next: usize,
+    generation: usize,
+    // The generation each hart flushed its TLB in, entries older than it may be of another owner
+    flushed: [usize; CPU_NUM],
+}
+
+lazy_static! {
+    static ref ALLOCATOR: SpinNoIrqLock<Allocator> = SpinNoIrqLock::new(Allocator {
+        owners: Vec::new(),
+        next: 1,
+        generation: 0,
+        flushed: [0; CPU_NUM],
+    });
+}
+
+// Find out how many ASIDs the hart has (the unimplemented bits are read as zeros)
+pub fn initialize() {
+    let token = satp::read().bits();
+    let bits = unsafe {
+        set_satp(token | ASID_MASK << ASID_SHIFT);
+        let bits = satp::read().bits() >> ASID_SHIFT & ASID_MASK;
+        set_satp(token);
+        bits
+    };
+    let count = (bits + 1).min(MAX_ASID_COUNT);
+    ALLOCATOR.lock().owners.resize(count, 0);
+    println!("[kernel] {} ASIDs in use", count);
+}
+
+unsafe fn set_satp(token: usize) {
+    asm!("csrw satp, $0" :: "r"(token) :: "volatile");
+}
+
+pub fn token(ppn: usize, asid: usize) -> usize {
+    SV39 | asid << ASID_SHIFT | ppn
+}
+
+pub fn asid_of(token: usize) -> usize {
+    token >> ASID_SHIFT & ASID_MASK
+}
+
+pub fn ppn_of(token: usize) -> usize {
+    token & PPN_MASK
+}
+
+impl Allocator {
+    // Every page table has at most one ASID in a generation, hint is checked first
+    fn find(&self, ppn: usize, hint: usize) -> Option<usize> {
+        if hint != 0 && hint < self.owners.len() && self.owners[hint] == ppn {
+            return Some(hint);
+        }
+        self.owners
+            .iter()
+            .skip(1)
+            .position(|&owner| owner == ppn)
+            .map(|i| i + 1)
+    }
+
+    fn assign(&mut self, ppn: usize, hint: usize) -> usize {
+        let count = self.owners.len();
+        if count <= 1 {
+            return 0;
+        }
+        if let Some(asid) = self.find(ppn, hint) {
+            return asid;
+        }
+        if self.next == count {
+            // Stale entries with a reused ASID are flushed by each hart before it switches to it
+            for owner in self.owners.iter_mut() {
+                *owner = 0;
+            }
+            self.next = 1;
+            self.generation += 1;
+        }
+        let asid = self.next;
+        self.next += 1;
+        self.owners[asid] = ppn;
+        asid
+    }
+}
+
+// The ASID of the page table with root ppn, hint is the one it had before
+pub fn assign(ppn: usize, hint: usize) -> usize {
+    ALLOCATOR.lock().assign(ppn, hint)
+}
+
+// Like assign, for this hart to switch to the page table, and whether its TLB must be flushed
+// after the switch: once in every generation, and every time without ASIDs (all share 0)
+pub fn assign_to_switch(ppn: usize, hint: usize) -> (usize, bool) {
+    let mut allocator = ALLOCATOR.lock();
+    let asid = allocator.assign(ppn, hint);
+    let hart = cpu::id();
+    let flush = asid == 0 || allocator.flushed[hart] != allocator.generation;
+    allocator.flushed[hart] = allocator.generation;
+    (asid, flush)
+}
+
+// The ASID the page table has in this generation, its entries left with an older one are never used
+pub fn current(ppn: usize, hint: usize) -> Option<usize> {
+    ALLOCATOR.lock().find(ppn, hint)
+}
+
+// The page table is gone, its root frame may be used by another one
+// The ASID is not given out again till the next generation
+pub fn release(ppn: usize, hint: usize) {
+    let mut allocator = ALLOCATOR.lock();
+    if let Some(asid) = allocator.find(ppn, hint) {
+        allocator.owners[asid] = 0;
+    }
+}
@@ -6,13 +6,16 @@ use riscv::paging::{PageTableEntry, PageTableFlags as EF};
 pub struct PageEntry {
     pub entry: &'static mut PageTableEntry,
     pub page: VirtualPage,
+    // The ASID to flush with, None if the TLB has nothing of the page table
+    pub asid: Option<usize>,
 }
 
 impl PageEntry {
     // TLB refresh
     pub fn update(&mut self) {
-        // asid is the process id (0 for kernel, I guess)
-        unsafe { sfence_vma(0, self.page.start_address().as_usize()) }
+        if let Some(asid) = self.asid {
+            unsafe { sfence_vma(asid, self.page.start_address().as_usize()) }
+        }
     }
 
     // TODO: to deeply understand the meaning of the flags
@@ -2,6 +2,7 @@ use crate::memory::{frame_alloc, frame_dealloc};
 use riscv::addr::Frame;
 use riscv::paging::{FrameAllocator, FrameDeallocator};
 
+pub mod asid;
 pub mod entry;
 pub mod range;
 pub mod table;
@@ -1,14 +1,22 @@
 use crate::consts::*;
+use crate::memory::manager::paging::asid;
 use crate::memory::manager::paging::entry::PageEntry;
 use crate::memory::manager::paging::FrameAllocatorForPaging;
-use crate::memory::{frame_alloc, paddr_to_vaddr};
+use crate::memory::{frame_alloc, frame_dealloc, paddr_to_vaddr};
+use core::cell::Cell;
 use riscv::addr::{Frame, Page, PhysAddr, VirtAddr};
-use riscv::asm::sfence_vma_all;
+use riscv::asm::{sfence_vma, sfence_vma_all};
 use riscv::paging::{
     Mapper, PageTable as PageTableEntryArray, PageTableEntry, PageTableFlags as EF, Rv39PageTable,
 };
 use riscv::register::satp;
 
+// The root entries for [PHYSICAL_MEMORY_OFFSET, 2^64), the kernel space
+const KERNEL_ENTRIES: core::ops::Range<usize> = ((PHYSICAL_MEMORY_OFFSET >> 30) & 0x1ff)..512;
+
+// Root of the kernel page table, the others share its tables for the kernel space
+static mut KERNEL_ROOT: Option<Frame> = None;
+
 // Note the table could only map virtual page to physical ones, but not manage the segments of memory (like bss or ...)
 pub struct PageTable {
     // Rv39PageTable is 3-level structure
@@ -16,6 +24,8 @@ pub struct PageTable {
     // Root frame of the 3-level structure
     root: Frame,
     entry: Option<PageEntry>,
+    // The ASID it had last time, it may be given to another one in a new generation
+    asid: Cell<usize>,
 }
 
 impl PageTable {
@@ -28,11 +38,19 @@ impl PageTable {
         // Note that the size of the space is fixed (2^9 entry for riscv64 because page size is 2^12 (8 bytes for each entry))
         let table = unsafe { &mut *(paddr_to_vaddr(paddr) as *mut PageTableEntryArray) };
         table.zero();
+        // Only the root entries are copied, so the kernel space must be built before
+        if let Some(kernel) = unsafe { KERNEL_ROOT.clone() } {
+            let kernel = unsafe { table_of(kernel) };
+            for i in KERNEL_ENTRIES {
+                table[i] = kernel[i].clone();
+            }
+        }
 
         PageTable {
             page_table: Rv39PageTable::new(table, PHYSICAL_MEMORY_OFFSET),
             root: frame,
             entry: None,
+            asid: Cell::new(0),
         }
     }
 
@@ -47,15 +65,47 @@ impl PageTable {
         self.page_table
             .map_to(page, frame, flags, &mut FrameAllocatorForPaging)
             .unwrap()
-            .flush();
+            .ignore();
+        self.flush_page(vaddr);
         self.get_entry(vaddr).unwrap()
     }
 
+    // Map a 2 MB page, both of the addresses are 2 MB aligned
+    pub fn map_megapage(&mut self, vaddr: usize, paddr: usize, flags: EF) {
+        let root = unsafe { table_of(self.root.clone()) };
+        let entry = &mut root[(vaddr >> 30) & 0x1ff];
+        if !entry.flags().contains(EF::VALID) {
+            let frame = frame_alloc().unwrap();
+            unsafe { table_of(frame.clone()) }.zero();
+            entry.set(frame, EF::VALID);
+        }
+        let table = unsafe { table_of(Frame::of_addr(entry.addr())) };
+        table[(vaddr >> 21) & 0x1ff].set(Frame::of_addr(PhysAddr::new(paddr)), flags);
+        unsafe {
+            sfence_vma_all();
+        }
+    }
+
+    // Let the page tables created later share the kernel space of this one
+    // Every root entry of it gets a table, so the pages mapped later are shared too
+    pub unsafe fn set_kernel(&self) {
+        let root = table_of(self.root.clone());
+        for i in KERNEL_ENTRIES {
+            if !root[i].flags().contains(EF::VALID) {
+                let frame = frame_alloc().unwrap();
+                table_of(frame.clone()).zero();
+                root[i].set(frame, EF::VALID);
+            }
+        }
+        KERNEL_ROOT = Some(self.root.clone());
+    }
+
     // Unmap a virtual page
     pub fn unmap(&mut self, vaddr: usize) {
         let page = Page::of_addr(VirtAddr::new(vaddr));
         let (_, flush) = self.page_table.unmap(page).unwrap();
-        flush.flush();
+        flush.ignore();
+        self.flush_page(vaddr);
     }
 
     // Get the mapping
@@ -63,16 +113,62 @@ impl PageTable {
         let page = Page::of_addr(VirtAddr::new(vaddr));
         if let Ok(entry) = self.page_table.ref_entry(page.clone()) {
             let entry = unsafe { &mut *(entry as *mut PageTableEntry) };
-            self.entry = Some(PageEntry { entry, page });
+            let asid = self.cached_asid();
+            self.entry = Some(PageEntry { entry, page, asid });
             Some(self.entry.as_mut().unwrap())
         } else {
             None
         }
     }
 
-    // Get token (root.number is the physical page number)
+    // Get token (root.number is the physical page number), with the ASID of this generation
     pub fn token(&self) -> usize {
-        self.root.number() | (8 << 60)
+        let ppn = self.root.number();
+        let asid = if is_kernel(ppn) {
+            0
+        } else {
+            asid::assign(ppn, self.asid.get())
+        };
+        self.asid.set(asid);
+        asid::token(ppn, asid)
+    }
+
+    pub fn root_ppn(&self) -> usize {
+        self.root.number()
+    }
+
+    // A saved token may have an ASID given to another page table since, take a new one
+    // It's for this hart to switch to, true if the TLB must be flushed after that
+    // (the ASID may have stale entries of another page table)
+    pub fn refresh_token(token: usize) -> (usize, bool) {
+        let ppn = asid::ppn_of(token);
+        if is_kernel(ppn) {
+            return (asid::token(ppn, 0), false);
+        }
+        let (asid, flush) = asid::assign_to_switch(ppn, asid::asid_of(token));
+        (asid::token(ppn, asid), flush)
+    }
+
+    // The ASID the entries of this table in the TLB of the current hart may have
+    // (the changes other harts must see are flushed there by the memory manager)
+    fn cached_asid(&self) -> Option<usize> {
+        let ppn = self.root.number();
+        let current = Self::current_token();
+        if asid::ppn_of(current) == ppn {
+            Some(asid::asid_of(current))
+        } else if is_kernel(ppn) {
+            Some(0)
+        } else {
+            asid::current(ppn, self.asid.get())
+        }
+    }
+
+    fn flush_page(&self, vaddr: usize) {
+        if let Some(asid) = self.cached_asid() {
+            unsafe {
+                sfence_vma(asid, vaddr);
+            }
+        }
     }
 
     // Change satp register for another page table (switching)
@@ -85,20 +181,62 @@ impl PageTable {
         satp::read().bits()
     }
 
-    // TLB refresh
-    fn flush_tlb() {
-        unsafe {
+    // Activate self
+    pub unsafe fn activate(&self) {
+        Self::activate_token(self.token());
+    }
+
+    // Activate the page table by its token (maybe owned by others)
+    // Only done at boot and by exec, so the whole TLB is flushed (the boot page table has ASID 0 too)
+    pub unsafe fn activate_token(token: usize) {
+        let (token, _) = Self::refresh_token(token);
+        if token != Self::current_token() {
+            Self::set_token(token);
             sfence_vma_all();
         }
     }
+}
 
-    // Activate self
-    pub unsafe fn activate(&self) {
-        let old_token = Self::current_token();
-        let new_token = self.token();
-        if new_token != old_token {
-            Self::set_token(new_token);
-            Self::flush_tlb();
+// Free the root and all the intermediate tables (the mapped frames are freed by the areas)
+// The tables of the kernel space belong to the kernel page table
+impl Drop for PageTable {
+    fn drop(&mut self) {
+        unsafe {
+            let root = table_of(self.root.clone());
+            for i in 0..KERNEL_ENTRIES.start {
+                free_entry(&root[i], 1);
+            }
+            asid::release(self.root.number(), self.asid.get());
+            frame_dealloc(self.root.clone());
         }
     }
 }
+
+fn is_kernel(ppn: usize) -> bool {
+    unsafe { KERNEL_ROOT.as_ref() }.map_or(false, |root| root.number() == ppn)
+}
+
+unsafe fn table_of(frame: Frame) -> &'static mut PageTableEntryArray {
+    &mut *(paddr_to_vaddr(frame.start_address().as_usize()) as *mut PageTableEntryArray)
+}
+
+// Sv39 has 3 levels, the tables of level 0 point to the pages
+unsafe fn free_table(frame: Frame, level: usize) {
+    if level > 0 {
+        let table = table_of(frame.clone());
+        for i in 0..512 {
+            free_entry(&table[i], level - 1);
+        }
+    }
+    frame_dealloc(frame);
+}
+
+// Free the table of the given level an entry points to, if it does
+unsafe fn free_entry(entry: &PageTableEntry, level: usize) {
+    let flags = entry.flags();
+    // Valid but not RWX means pointing to the next level
+    if flags.contains(EF::VALID) && !flags.intersects(EF::READABLE | EF::WRITABLE | EF::EXECUTABLE)
+    {
+        free_table(Frame::of_addr(entry.addr()), level);
+    }
+}
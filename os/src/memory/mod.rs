@@ -1,11 +1,18 @@
 #![allow(dead_code)]
 
 use crate::consts::*;
-use crate::memory::frame_allocator::LinearFrameAllocator;
+use crate::drivers::{devices, plic};
+use crate::memory::frame_allocator::{FrameAllocator, SegmentTreeFrameAllocator};
 use crate::memory::manager::attr::MemoryAttr;
-use crate::memory::manager::handler::Linear;
+use crate::memory::manager::handler::{ByFrame, Linear};
+use crate::memory::manager::paging::asid;
+use crate::memory::manager::paging::table::PageTable;
 use crate::memory::manager::Manager;
+use crate::sbi;
+use crate::sync::spin_no_irq::SpinNoIrqLock;
+use alloc::collections::BTreeMap;
 use buddy_system_allocator::LockedHeap;
+use lazy_static::*;
 use riscv::addr::Frame;
 use riscv::register::sstatus;
 use spin::Mutex;
@@ -13,21 +20,32 @@ use spin::Mutex;
 mod frame_allocator;
 pub mod manager;
 
-// Frame allocator
-static FRAME_ALLOCATOR: Mutex<LinearFrameAllocator> = Mutex::new(LinearFrameAllocator {
-    flags: [false; MAX_PHYSICAL_PAGES],
-    offset: 0,
-    size: 0,
-});
+// Frame allocator (any FrameAllocator can be used here)
+static FRAME_ALLOCATOR: Mutex<SegmentTreeFrameAllocator> =
+    Mutex::new(SegmentTreeFrameAllocator::new());
+
+// Reference counts of the frames shared by copy-on-write (not in the map means only one owner)
+lazy_static! {
+    static ref SHARED_FRAMES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+}
 
 // Dynamic allocator on heap
 #[global_allocator]
 static DYNAMIC_ALLOCATOR: LockedHeap = LockedHeap::empty();
 static mut HEAP: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 
+// The remapped kernel page table, shared by all the harts
+static mut KERNEL_TOKEN: usize = 0;
+// Set up by the boot hart, the kernel stacks are mapped into it later
+static mut KERNEL_SPACE: Option<SpinNoIrqLock<Manager>> = None;
+
+// End of the physical memory we use, mapped in every address space
+static mut PHYSICAL_MEMORY_END: usize = 0;
+
 pub fn initialize(begin: usize, end: usize) {
     unsafe {
         sstatus::set_sum();
+        PHYSICAL_MEMORY_END = end * PAGE_SIZE;
     }
     FRAME_ALLOCATOR.lock().initialize(begin, end);
     unsafe {
@@ -36,15 +54,22 @@ pub fn initialize(begin: usize, end: usize) {
             .init(HEAP.as_ptr() as usize, KERNEL_HEAP_SIZE);
     }
 
-    // Remapping of different areas in kernel code and generate a new page table
-    // The remapped in this function will be only used in kernel threads
-    // TODO: can the remap process be deleted in new user thread?
+    asid::initialize();
+    // Build the kernel space once, the user page tables share it
     kernel_remap();
     println!("[kernel] Memory initialized.");
 }
 
+// The other harts only have to switch to the kernel page table
+pub fn initialize_other() {
+    unsafe {
+        sstatus::set_sum();
+        PageTable::activate_token(KERNEL_TOKEN);
+    }
+}
+
 pub fn kernel_remap() {
-    let mut manager = Manager::new();
+    let mut manager = Manager::new_kernel();
     extern "C" {
         fn boot_stack();
         fn boot_stack_top();
@@ -56,23 +81,68 @@ pub fn kernel_remap() {
         Linear::new(PHYSICAL_MEMORY_OFFSET),
         None,
     );
+    unsafe {
+        // Before the token is taken, so the kernel has ASID 0
+        manager.set_kernel();
+        manager.activate();
+        KERNEL_TOKEN = manager.token();
+    }
+    // Used by the kernel threads forever, never drop it
+    unsafe {
+        KERNEL_SPACE = Some(SpinNoIrqLock::new(manager));
+    }
+}
+
+// Map [start, end) of the kernel space to new frames, false if there are not enough
+pub fn kernel_map(start: usize, end: usize) -> bool {
+    let mut kernel = kernel_space().lock();
+    // One more for a page table
+    if free_frame_count() < (end - start) / PAGE_SIZE + 1 {
+        return false;
+    }
+    kernel.push(start, end, MemoryAttr::new(), ByFrame::new(), None)
+}
+
+// Unmap [start, end) mapped by kernel_map and free the frames
+pub fn kernel_unmap(start: usize, end: usize) {
+    kernel_space().lock().remove_range(start, end);
+}
+
+// Drop [start, end) from the TLB of every hart, with any ASID
+pub fn flush_harts(start: usize, end: usize) {
+    let harts = devices().harts.min(CPU_NUM);
+    sbi::remote_sfence_vma((1 << harts) - 1, start, end - start);
+}
+
+fn kernel_space() -> &'static SpinNoIrqLock<Manager> {
+    unsafe { KERNEL_SPACE.as_ref().unwrap() }
+}
+
+// Registers of the devices, in the kernel space since the drivers and the interrupts
+// may run on any page table
+pub fn map_devices(manager: &mut Manager) {
+    let devices = devices();
+    for &(start, end) in plic::MAPPED.iter() {
+        map_device(manager, devices.plic.base + start, end - start);
+    }
+    map_device(manager, devices.rtc.base, devices.rtc.size);
+    map_device(manager, devices.uart.base, devices.uart.size);
+    for device in devices.virtio() {
+        map_device(manager, device.base, device.size);
+    }
+}
+
+// [paddr, paddr + size) rounded to pages
+fn map_device(manager: &mut Manager, paddr: usize, size: usize) {
+    let start = paddr / PAGE_SIZE * PAGE_SIZE;
+    let end = (paddr + size.max(1) + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
     manager.push(
-        paddr_to_vaddr(0x0c00_2000),
-        paddr_to_vaddr(0x0c00_3000),
-        MemoryAttr::new(),
-        Linear::new(PHYSICAL_MEMORY_OFFSET),
-        None,
-    );
-    manager.push(
-        paddr_to_vaddr(0x1000_0000),
-        paddr_to_vaddr(0x1000_1000),
+        paddr_to_vaddr(start),
+        paddr_to_vaddr(end),
         MemoryAttr::new(),
         Linear::new(PHYSICAL_MEMORY_OFFSET),
         None,
     );
-    unsafe {
-        manager.activate();
-    }
 }
 
 #[alloc_error_handler]
@@ -81,13 +151,65 @@ fn alloc_error_handler(_: core::alloc::Layout) -> ! {
 }
 
 pub fn frame_alloc() -> Option<Frame> {
-    Some(Frame::of_ppn(FRAME_ALLOCATOR.lock().alloc()))
+    FRAME_ALLOCATOR.lock().alloc().map(Frame::of_ppn)
 }
 
 pub fn frame_dealloc(frame: Frame) {
     FRAME_ALLOCATOR.lock().dealloc(frame.number());
 }
 
+// count frames starting from a multiple of align (in pages)
+pub fn frame_alloc_contiguous(count: usize, align: usize) -> Option<Frame> {
+    FRAME_ALLOCATOR
+        .lock()
+        .alloc_contiguous(count, align)
+        .map(Frame::of_ppn)
+}
+
+pub fn frame_dealloc_contiguous(frame: Frame, count: usize, align: usize) {
+    FRAME_ALLOCATOR
+        .lock()
+        .dealloc_contiguous(frame.number(), count, align);
+}
+
+// Whether count pages can be mapped, even lazy ones take page table entries
+// (a table for every 512 pages, the tables above and the ones partly used)
+pub fn enough_frames(count: usize) -> bool {
+    free_frame_count() >= count + count / 256 + 4
+}
+
+pub fn free_frame_count() -> usize {
+    FRAME_ALLOCATOR.lock().free_count()
+}
+
+// One more page table refers to the frame
+pub fn frame_share(frame: Frame) {
+    *SHARED_FRAMES.lock().entry(frame.number()).or_insert(1) += 1;
+}
+
+pub fn frame_is_shared(frame: Frame) -> bool {
+    SHARED_FRAMES.lock().contains_key(&frame.number())
+}
+
+// Drop one reference to a shared frame, return whether others are still referring to it
+pub fn frame_unshare(frame: Frame) -> bool {
+    let mut shared = SHARED_FRAMES.lock();
+    match shared.get_mut(&frame.number()) {
+        Some(count) => {
+            *count -= 1;
+            if *count == 1 {
+                shared.remove(&frame.number());
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn physical_memory_end() -> usize {
+    unsafe { PHYSICAL_MEMORY_END }
+}
+
 pub fn paddr_to_vaddr(paddr: usize) -> usize {
     paddr + PHYSICAL_MEMORY_OFFSET
 }
@@ -1,3 +1,4 @@
+use crate::memory::manager::paging::table::PageTable;
 use crate::trap::frame::TrapFrame;
 use core::mem::zeroed;
 use riscv::register::sstatus;
@@ -55,6 +56,30 @@ impl Content {
         }
     }
 
+    // Same as the parent, except a0 (return value of fork)
+    fn new_fork(frame: &TrapFrame, satp: usize) -> Self {
+        extern "C" {
+            fn __trap_ret();
+        };
+        Content {
+            ra: __trap_ret as usize,
+            satp,
+            s: [0; 12],
+            frame: {
+                let mut frame = frame.clone();
+                frame.x[10] = 0;
+                frame
+            },
+        }
+    }
+
+    // Same as fork, but running on another user stack
+    fn new_clone(frame: &TrapFrame, user_stack: usize, satp: usize) -> Self {
+        let mut content = Content::new_fork(frame, satp);
+        content.frame.x[2] = user_stack;
+        content
+    }
+
     unsafe fn push_at(self, stack_top: usize) -> Context {
         let ptr = (stack_top as *mut Content).sub(1); // sub means minus sizeof(Content)
         *ptr = self;
@@ -74,13 +99,22 @@ pub struct Context {
 impl Context {
     #[naked] // Do not use *prologue* and *epilogue*, because the asm has already done
     #[inline(never)] // Do not inline, because we're using call/ret to switch thread
-    pub unsafe extern "C" fn switch(&mut self, _target: &mut Context) {
+    pub unsafe extern "C" fn switch(&mut self, _target: &mut Context, _flush: bool) {
         asm!(include_str!("switch.asm") :::: "volatile");
     }
 
     pub fn null() -> Context {
         Context { content_addr: 0 }
     }
+
+    // The ASID in the saved token may be given to another address space in a new generation
+    // Return whether switch must flush the TLB
+    pub unsafe fn refresh_token(&self) -> bool {
+        let content = &mut *(self.content_addr as *mut Content);
+        let (token, flush) = PageTable::refresh_token(content.satp);
+        content.satp = token;
+        flush
+    }
 }
 
 impl Context {
@@ -98,6 +132,19 @@ impl Context {
         Content::new_user(entry, user_stack, satp).push_at(kernel_stack)
     }
 
+    pub unsafe fn new_fork(frame: &TrapFrame, kernel_stack: usize, satp: usize) -> Context {
+        Content::new_fork(frame, satp).push_at(kernel_stack)
+    }
+
+    pub unsafe fn new_clone(
+        frame: &TrapFrame,
+        user_stack: usize,
+        kernel_stack: usize,
+        satp: usize,
+    ) -> Context {
+        Content::new_clone(frame, user_stack, satp).push_at(kernel_stack)
+    }
+
     // We can use the trap frame (as an initialization) to pass arguments
     pub unsafe fn append_args(&self, args: [usize; 3]) {
         let content = &mut *(self.content_addr as *mut Content);
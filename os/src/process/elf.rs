@@ -5,12 +5,13 @@ use xmas_elf::program::{Flags, SegmentData, Type};
 use xmas_elf::ElfFile;
 
 pub trait ElfExt {
-    fn new_manager(&self) -> Manager;
+    fn new_manager(&self) -> Result<Manager, &'static str>;
 }
 
 // The format of a user program will be ELF
 impl ElfExt for ElfFile<'_> {
-    fn new_manager(&self) -> Manager {
+    // Fail if there are not enough frames for the segments
+    fn new_manager(&self) -> Result<Manager, &'static str> {
         let mut manager = Manager::new();
         for area in self.program_iter() {
             if area.get_type() != Ok(Type::Load) {
@@ -27,15 +28,18 @@ impl ElfExt for ElfFile<'_> {
                 _ => unreachable!(),
             };
 
-            manager.push(
+            let pushed = manager.push(
                 vaddr,
                 vaddr + size,
                 area.flags().to_attr(),
                 ByFrame::new(),
                 Some((data.as_ptr() as usize, data.len())),
             );
+            if !pushed {
+                return Err("Not enough memory for the program.");
+            }
         }
-        manager
+        Ok(manager)
     }
 }
 
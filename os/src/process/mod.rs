@@ -1,22 +1,42 @@
+use crate::consts::CPU_NUM;
+use crate::cpu;
+use crate::drivers::devices;
 use crate::fs::{INodeExt, ROOT_INODE};
+use crate::interrupt::{disable_and_store, restore};
+use crate::memory::free_frame_count;
 use crate::process::pool::ThreadPool;
+use crate::process::process::Process;
 use crate::process::processor::Processor;
-use crate::process::scheduler::RoundRobinScheduler;
 use crate::process::thread::Thread;
-use alloc::boxed::Box;
+use crate::sync::spin_no_irq::SpinNoIrqLock;
+use crate::trap::frame::TrapFrame;
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+use spin::Mutex;
 
 mod context;
 mod elf;
 mod pool;
+mod process;
 mod processor;
 mod scheduler;
-mod stack;
+pub mod stack;
 mod thread;
 
+pub use crate::process::scheduler::MAX_PRIORITY;
+
 pub type ThreadID = usize;
 pub type ExitCode = usize;
 
-static PROCESSOR: Processor = Processor::new();
+// One for each hart
+static PROCESSORS: [Processor; CPU_NUM] = [
+    Processor::new(),
+    Processor::new(),
+    Processor::new(),
+    Processor::new(),
+];
+
+// Threads of all the harts, set up by the boot hart before the others start
+static mut POOL: Option<&'static SpinNoIrqLock<ThreadPool>> = None;
 
 #[no_mangle]
 pub extern "C" fn test_thread(arg: usize) -> ! {
@@ -28,64 +48,221 @@ pub extern "C" fn test_thread(arg: usize) -> ! {
     exit(0);
 }
 
+// Every frame of a user program must be returned after it exits
+// Nothing else runs meanwhile, the shell is started after the test
+#[no_mangle]
+pub extern "C" fn test_frame_leak(times: usize) -> ! {
+    // The first run may leave page tables in the kernel space for its kernel stack
+    let run = || {
+        let tid = execute("rust/hello", Some(current_tid())).unwrap();
+        wait(Some(tid)).unwrap();
+    };
+    run();
+    let free = free_frame_count();
+    for _i in 0..times {
+        run();
+    }
+    assert_eq!(free, free_frame_count(), "Frames leaked");
+    println!("[kernel] No frame leaked after {} runs.", times);
+    execute("rust/shell", None);
+    exit(0);
+}
+
+// A thread may be moved to another hart by the timer, so interrupts are disabled
+// from choosing the processor of this hart till the call returns
+fn with_processor<T>(f: impl FnOnce(&Processor) -> T) -> T {
+    let flags = disable_and_store();
+    let ret = f(&PROCESSORS[cpu::id()]);
+    restore(flags);
+    ret
+}
+
 pub fn run() {
-    PROCESSOR.run();
+    with_processor(|processor| processor.run());
 }
 
 pub fn exit(code: ExitCode) -> ! {
-    PROCESSOR.exit(code)
+    disable_and_store();
+    PROCESSORS[cpu::id()].exit(code)
 }
 
 pub fn tick() {
-    PROCESSOR.tick();
+    with_processor(|processor| processor.tick());
 }
 
 pub fn sleep() {
-    PROCESSOR.sleep();
+    sleep_with(|_| {});
+}
+
+// Sleep, and 'prepare' gets the tid to register for waking up
+// False if there is no current thread (prepare is not called then)
+pub fn sleep_with(prepare: impl FnOnce(ThreadID)) -> bool {
+    with_processor(|processor| processor.sleep_with(prepare))
 }
 
 pub fn wake_up(id: usize) {
-    PROCESSOR.wake_up(id);
+    with_processor(|processor| processor.wake_up(id));
+}
+
+// For the current thread
+pub fn set_priority(priority: usize) {
+    with_processor(|processor| processor.set_priority(priority));
 }
 
 pub fn current_tid() -> usize {
-    PROCESSOR.current_tid()
+    with_processor(|processor| processor.current_tid())
+}
+
+fn current_thread() -> &'static mut Thread {
+    with_processor(|processor| processor.current_thread())
+}
+
+// Every thread of a process holds it, so the others can be counted
+// Only the current thread can add one if it's the last
+pub fn is_only_thread() -> bool {
+    Arc::strong_count(current_thread().process.as_ref().unwrap()) == 1
+}
+
+// None if the pool is full
+fn add_thread(thread: Box<Thread>, parent: Option<ThreadID>) -> Option<ThreadID> {
+    with_processor(|processor| processor.add_thread(thread, parent))
+}
+
+// Panic if called by a kernel thread
+pub fn current_process() -> Arc<Mutex<Process>> {
+    current_thread().process.clone().unwrap()
+}
+
+// None if the new thread can't get a kernel stack or a slot in the pool
+pub fn fork(frame: &TrapFrame) -> Option<ThreadID> {
+    let thread = current_thread().fork(frame, current_tid())?;
+    add_thread(thread, Some(current_tid()))
+}
+
+// New thread sharing the current process, nobody waits for it
+// None if it can't get a kernel stack or a slot in the pool
+pub fn clone_thread(frame: &TrapFrame, user_stack: usize) -> Option<ThreadID> {
+    let thread = current_thread().clone_thread(frame, user_stack)?;
+    add_thread(thread, None)
+}
+
+// Replace the program of the current process and close the close-on-exec files,
+// the frame is set for the new one
+// Return argc (it will be in a0)
+pub fn exec(
+    data: &[u8],
+    args: Vec<String>,
+    envs: Vec<String>,
+    frame: &mut TrapFrame,
+) -> Result<usize, &'static str> {
+    let (info, closed) = {
+        let process = current_process();
+        let mut process = process.lock();
+        let info = process.exec(data, &args, &envs)?;
+        (info, process.files.close_on_exec())
+    };
+    // Dropped out of the lock, closing a pipe end wakes up others
+    drop(closed);
+    for x in frame.x.iter_mut() {
+        *x = 0;
+    }
+    frame.x[2] = info.sp;
+    frame.x[11] = info.argv;
+    frame.sepc = info.entry;
+    Ok(info.argc)
+}
+
+pub fn wait(target: Option<ThreadID>) -> Option<(ThreadID, ExitCode)> {
+    loop {
+        match with_processor(|processor| processor.wait(target)) {
+            Ok(Some(result)) => break Some(result),
+            // Woken up by an exited child
+            Ok(None) => continue,
+            Err(()) => break None,
+        }
+    }
+}
+
+pub fn handle_page_fault(vaddr: usize) -> bool {
+    with_processor(|processor| processor.handle_page_fault(vaddr))
 }
 
 pub fn initialize() {
-    let scheduler = RoundRobinScheduler::new(2);
-    let pool = ThreadPool::new(128, Box::new(scheduler));
-    let idle = Thread::new_kernel(Processor::idle_main as usize);
-    idle.append_args([&PROCESSOR as *const Processor as usize, 0, 0]);
-    PROCESSOR.initialize(idle, Box::new(pool));
+    // Chosen by 'scheduler=...' in the boot arguments (`make run scheduler=...`)
+    let mut name = devices().bootarg("scheduler").unwrap_or("stride");
+    if scheduler::from_name(name, 2).is_none() {
+        println!("[kernel] Unknown scheduler {}.", name);
+        name = "stride";
+    }
+    let scheduler = scheduler::from_name(name, 2).unwrap();
+    println!("[kernel] Using {} scheduler.", name);
+    let pool = ThreadPool::new(128, scheduler);
+    let pool: &'static SpinNoIrqLock<ThreadPool> = Box::leak(Box::new(SpinNoIrqLock::new(pool)));
+    unsafe {
+        POOL = Some(pool);
+    }
+    initialize_other();
+
+    #[cfg(feature = "self-test")]
+    add_thread(
+        {
+            let thread = Thread::new_kernel(test_frame_leak as usize);
+            thread.append_args([16, 0, 0]);
+            thread
+        },
+        None,
+    );
 
     // User shell
+    #[cfg(not(feature = "self-test"))]
     execute("rust/shell", None);
 
     /*
     // Kernel thread test
     for i in 0..5 {
-        PROCESSOR.add_thread({
-            let thread = Thread::new_kernel(test_thread as usize);
-            thread.append_args([i, 0, 0]);
-            thread
-        });
+        add_thread(
+            {
+                let thread = Thread::new_kernel(test_thread as usize);
+                thread.append_args([i, 0, 0]);
+                thread
+            },
+            None,
+        );
     }
     */
 }
 
-pub fn execute(path: &str, host: Option<ThreadID>) -> bool {
+// Every hart has its own idle thread working on the shared pool
+pub fn initialize_other() {
+    let processor = &PROCESSORS[cpu::id()];
+    let idle = Thread::new_kernel(Processor::idle_main as usize);
+    idle.append_args([processor as *const Processor as usize, 0, 0]);
+    processor.initialize(idle, unsafe { POOL.unwrap() });
+}
+
+// Return the tid of the new thread
+pub fn execute(path: &str, parent: Option<ThreadID>) -> Option<ThreadID> {
     let found = ROOT_INODE.lookup(path);
     match found {
         Ok(inode) => {
             let data = inode.read_as_vec().unwrap();
-            let thread = Thread::new_user(data.as_slice(), host);
-            PROCESSOR.add_thread(thread);
-            true
+            let args = [String::from(path)];
+            let thread = match Thread::new_user(data.as_slice(), &args, &[], parent) {
+                Ok(thread) => thread,
+                Err(message) => {
+                    println!("{}", message);
+                    return None;
+                }
+            };
+            let tid = add_thread(thread, parent);
+            if tid.is_none() {
+                println!("Too many threads.");
+            }
+            tid
         }
         Err(_) => {
             println!("Program not found.");
-            false
+            None
         }
     }
 }
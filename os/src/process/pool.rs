@@ -1,6 +1,6 @@
 use crate::process::scheduler::Scheduler;
 use crate::process::thread::{Thread, ThreadInfo, ThreadStatus};
-use crate::process::ThreadID;
+use crate::process::{ExitCode, ThreadID};
 use alloc::boxed::Box;
 use alloc::vec::Vec;
 
@@ -21,22 +21,29 @@ impl ThreadPool {
         }
     }
 
-    fn alloc_id(&self) -> ThreadID {
-        for (i, info) in self.threads.iter().enumerate() {
-            if info.is_none() {
-                return i;
-            }
-        }
-        panic!("Thread alloc failed");
+    // None if all the slots are used
+    fn alloc_id(&self) -> Option<ThreadID> {
+        self.threads.iter().position(|info| info.is_none())
     }
 
-    pub fn add(&mut self, thread: Box<Thread>) {
-        let id = self.alloc_id();
+    // The thread is handed back if the pool is full, drop it without the pool locked
+    pub fn add(
+        &mut self,
+        thread: Box<Thread>,
+        parent: Option<ThreadID>,
+    ) -> Result<ThreadID, Box<Thread>> {
+        let id = match self.alloc_id() {
+            Some(id) => id,
+            None => return Err(thread),
+        };
         self.threads[id] = Some(ThreadInfo {
             status: ThreadStatus::Ready,
+            parent,
             thread: Some(thread),
+            waiting: false,
         });
         self.scheduler.push(id);
+        Ok(id)
     }
 
     // Acquire one from the pool and run
@@ -51,35 +58,122 @@ impl ThreadPool {
         }
     }
 
-    // Running for a long time or exit
-    pub fn retrieve(&mut self, id: ThreadID, thread: Box<Thread>) {
-        // Exited
-        if self.threads[id].is_none() {
-            return;
-        }
-
-        let mut info = self.threads[id].as_mut().unwrap();
-        info.thread = Some(thread);
-        if let ThreadStatus::Running(_) = info.status {
-            // Running -> Ready
-            info.status = ThreadStatus::Ready;
-            self.scheduler.push(id);
+    // Back from a hart: preempted, sleeping or exited
+    // An exited thread is handed back, drop it without the pool locked (closing files may wake up others)
+    pub fn retrieve(&mut self, id: ThreadID, thread: Box<Thread>) -> Option<Box<Thread>> {
+        let info = self.threads[id].as_mut().unwrap();
+        match info.status {
+            // Preempted, or woken up before it got back
+            ThreadStatus::Running(_) | ThreadStatus::Ready => {
+                info.status = ThreadStatus::Ready;
+                info.thread = Some(thread);
+                self.scheduler.push(id);
+                None
+            }
+            ThreadStatus::Sleeping => {
+                info.thread = Some(thread);
+                None
+            }
+            // Nobody can reap the slot before the thread is off its hart
+            ThreadStatus::Exited(code) => {
+                match info.parent {
+                    Some(parent) => {
+                        info.status = ThreadStatus::Zombie(code);
+                        let waiting = &mut self.threads[parent].as_mut().unwrap().waiting;
+                        if *waiting {
+                            *waiting = false;
+                            self.wake_up(parent);
+                        }
+                    }
+                    None => {
+                        self.threads[id] = None;
+                    }
+                }
+                Some(thread)
+            }
+            ThreadStatus::Zombie(_) => unreachable!(),
         }
     }
 
     // Check whether we need a switch when ticked
-    pub fn tick(&mut self) -> bool {
-        self.scheduler.tick()
+    pub fn tick(&mut self, current: ThreadID) -> bool {
+        self.scheduler.tick(current)
+    }
+
+    pub fn set_priority(&mut self, id: ThreadID, priority: usize) {
+        self.scheduler.set_priority(id, priority);
     }
 
+    // A sleeping thread still on its hart is scheduled when it's retrieved
     pub fn wake_up(&mut self, id: ThreadID) {
-        let thread = self.threads[id].as_mut().unwrap();
-        thread.status = ThreadStatus::Ready;
-        self.scheduler.push(id);
+        if let Some(info) = self.threads[id].as_mut() {
+            if let ThreadStatus::Sleeping = info.status {
+                info.status = ThreadStatus::Ready;
+                if info.thread.is_some() {
+                    self.scheduler.push(id);
+                }
+            }
+        }
     }
 
-    pub fn exit(&mut self, id: ThreadID) {
-        self.threads[id] = None;
+    // Keep a zombie entry for the parent, the entry is settled when the thread is retrieved
+    pub fn exit(&mut self, id: ThreadID, code: ExitCode) {
         self.scheduler.exit(id);
+
+        // Children are orphans now, nobody will reap them
+        for slot in self.threads.iter_mut() {
+            let mut reap = false;
+            if let Some(info) = slot {
+                if info.parent == Some(id) {
+                    info.parent = None;
+                    if let Some(thread) = &info.thread {
+                        if let Some(process) = &thread.process {
+                            process.lock().parent = None;
+                        }
+                    }
+                    // A thread still on its hart is freed when retrieved
+                    if let ThreadStatus::Zombie(_) = info.status {
+                        reap = true;
+                    }
+                }
+            }
+            if reap {
+                *slot = None;
+            }
+        }
+
+        self.threads[id].as_mut().unwrap().status = ThreadStatus::Exited(code);
+    }
+
+    // Reap an exited child (any child if target is None)
+    // Ok(None) means the children are still running, Err if there is no such child
+    pub fn wait(
+        &mut self,
+        parent: ThreadID,
+        target: Option<ThreadID>,
+    ) -> Result<Option<(ThreadID, ExitCode)>, ()> {
+        let mut found = false;
+        for (id, slot) in self.threads.iter_mut().enumerate() {
+            let mut exited = None;
+            if let Some(info) = slot {
+                if info.parent != Some(parent) || target.map_or(false, |target| target != id) {
+                    continue;
+                }
+                found = true;
+                // Not retrieved yet if only exited, the parent is woken up after that
+                if let ThreadStatus::Zombie(code) = info.status {
+                    exited = Some(code);
+                }
+            }
+            if let Some(code) = exited {
+                *slot = None;
+                return Ok(Some((id, code)));
+            }
+        }
+        if found {
+            Ok(None)
+        } else {
+            Err(())
+        }
     }
 }
@@ -0,0 +1,266 @@
+use crate::consts::{
+    MAX_USER_HEAP_SIZE, PAGE_SIZE, USER_SPACE_END, USER_STACK_OFFSET, USER_STACK_SIZE,
+};
+use crate::fs::file::FdTable;
+use crate::fs::ROOT_INODE;
+use crate::memory::enough_frames;
+use crate::memory::manager::attr::MemoryAttr;
+use crate::memory::manager::handler::ByFrameLazy;
+use crate::memory::manager::Manager;
+use crate::process::elf::ElfExt;
+use crate::process::ThreadID;
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::mem::size_of;
+use rcore_fs::vfs::{INode, Result};
+use xmas_elf::program::Type;
+use xmas_elf::{header, ElfFile};
+
+// Types of the auxiliary vector
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_ENTRY: usize = 9;
+
+// Resources shared by all the threads of a user program
+pub struct Process {
+    pub vm: Manager,
+    pub files: FdTable,
+    pub cwd: String,
+    // The program break, the heap area is [heap_start, brk) rounded up to pages
+    pub heap_start: usize,
+    pub brk: usize,
+    // The thread which created this process (None if it's gone)
+    pub parent: Option<ThreadID>,
+}
+
+// Where a user program starts: (entry, stack pointer, argc, argv)
+pub struct StartInfo {
+    pub entry: usize,
+    pub sp: usize,
+    pub argc: usize,
+    pub argv: usize,
+    // Where the heap starts, right after the highest segment
+    pub heap: usize,
+}
+
+impl Process {
+    pub fn new_user(
+        data: &[u8],
+        args: &[String],
+        envs: &[String],
+        parent: Option<ThreadID>,
+    ) -> core::result::Result<(Process, StartInfo), &'static str> {
+        let (vm, info) = load(data, args, envs)?;
+        let process = Process {
+            vm,
+            files: FdTable::new(),
+            cwd: String::from("/"),
+            heap_start: info.heap,
+            brk: info.heap,
+            parent,
+        };
+        Ok((process, info))
+    }
+
+    // Replace the address space with a new program, files (see process::exec for the
+    // close-on-exec ones) and the family are kept
+    pub fn exec(
+        &mut self,
+        data: &[u8],
+        args: &[String],
+        envs: &[String],
+    ) -> core::result::Result<StartInfo, &'static str> {
+        let (vm, info) = load(data, args, envs)?;
+        unsafe {
+            vm.activate();
+        }
+        // The old one is not in use now
+        self.vm = vm;
+        self.heap_start = info.heap;
+        self.brk = info.heap;
+        Ok(info)
+    }
+
+    // The address space is copy-on-write, opened files are shared
+    pub fn fork(&mut self, parent: ThreadID) -> Process {
+        Process {
+            vm: self.vm.fork(),
+            files: self.files.clone(),
+            cwd: self.cwd.clone(),
+            heap_start: self.heap_start,
+            brk: self.brk,
+            parent: Some(parent),
+        }
+    }
+
+    pub fn cwd_inode(&self) -> Result<Arc<dyn INode>> {
+        ROOT_INODE.lookup(&self.cwd)
+    }
+
+    // Move the program break, the pages are allocated when touched
+    // Return false if the heap can't be there, is over MAX_USER_HEAP_SIZE or has no frames
+    pub fn set_brk(&mut self, brk: usize) -> bool {
+        if brk < self.heap_start
+            || brk - self.heap_start > MAX_USER_HEAP_SIZE
+            || brk > USER_SPACE_END
+        {
+            return false;
+        }
+        // munmap took the heap away, start it again empty
+        if !self.vm.has_area_at(self.heap_start) {
+            self.vm.push(
+                self.heap_start,
+                self.heap_start,
+                MemoryAttr::new().set_user(),
+                ByFrameLazy::new(),
+                None,
+            );
+            self.brk = self.heap_start;
+        }
+        let end = (brk + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+        let old_end = (self.brk + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+        if end > old_end && !enough_frames((end - old_end) / PAGE_SIZE) {
+            return false;
+        }
+        if !self.vm.resize_area(self.heap_start, end) {
+            return false;
+        }
+        self.brk = brk;
+        true
+    }
+
+    // Relative paths start from the working directory
+    pub fn lookup(&self, path: &str) -> Result<Arc<dyn INode>> {
+        self.cwd_inode()?.lookup(path)
+    }
+}
+
+// Map the ELF and the user stack with the arguments on it
+fn load(
+    data: &[u8],
+    args: &[String],
+    envs: &[String],
+) -> core::result::Result<(Manager, StartInfo), &'static str> {
+    let elf = ElfFile::new(data)?;
+
+    match elf.header.pt2.type_().as_type() {
+        header::Type::Executable => {}
+        header::Type::SharedObject => {
+            return Err("Shared object is not supported.");
+        }
+        _ => {
+            return Err("Unsupported ELF type.");
+        }
+    }
+
+    let entry = elf.header.pt2.entry_point() as usize;
+    // The manager will add other areas into it
+    let mut vm = elf.new_manager()?;
+
+    // The heap is empty at first, it grows by brk
+    let heap = elf
+        .program_iter()
+        .filter(|ph| ph.get_type() == Ok(Type::Load))
+        .map(|ph| (ph.virtual_addr() + ph.mem_size()) as usize)
+        .max()
+        .map(|end| (end + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE)
+        .ok_or("No loadable segment.")?;
+    vm.push(
+        heap,
+        heap,
+        MemoryAttr::new().set_user(),
+        ByFrameLazy::new(),
+        None,
+    );
+
+    let user_stack = {
+        // User stack will be in a fixed space of kernel
+        let (bottom, top) = (USER_STACK_OFFSET, USER_STACK_OFFSET + USER_STACK_SIZE);
+        vm.push(
+            bottom,
+            top,
+            MemoryAttr::new().set_user(),
+            ByFrameLazy::new(),
+            None,
+        );
+        top
+    };
+
+    // Program headers are in the segment which starts from the beginning of the file
+    let phdr = elf
+        .program_iter()
+        .find(|ph| ph.get_type() == Ok(Type::Load) && ph.offset() == 0)
+        .map_or(0, |ph| {
+            ph.virtual_addr() as usize + elf.header.pt2.ph_offset() as usize
+        });
+    let auxv = [
+        (AT_PHDR, phdr),
+        (AT_PHENT, elf.header.pt2.ph_entry_size() as usize),
+        (AT_PHNUM, elf.header.pt2.ph_count() as usize),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_ENTRY, entry),
+    ];
+    let sp = push_args(&mut vm, user_stack, args, envs, &auxv)?;
+
+    let info = StartInfo {
+        entry,
+        sp,
+        argc: args.len(),
+        argv: sp + size_of::<usize>(),
+        heap,
+    };
+    Ok((vm, info))
+}
+
+// System V initial stack (from low to high):
+// argc, argv[], NULL, envp[], NULL, auxv[], AT_NULL, (padding), strings
+fn push_args(
+    vm: &mut Manager,
+    top: usize,
+    args: &[String],
+    envs: &[String],
+    auxv: &[(usize, usize)],
+) -> core::result::Result<usize, &'static str> {
+    let mut sp = top;
+    let mut push_str = |s: &String| -> core::result::Result<usize, &'static str> {
+        sp = sp
+            .checked_sub(s.len() + 1)
+            .ok_or("Arguments out of the stack")?;
+        vm.write(sp, s.as_bytes())?;
+        vm.write(sp + s.len(), &[0])?;
+        Ok(sp)
+    };
+    let argv = args
+        .iter()
+        .map(&mut push_str)
+        .collect::<core::result::Result<Vec<usize>, _>>()?;
+    let envp = envs
+        .iter()
+        .map(&mut push_str)
+        .collect::<core::result::Result<Vec<usize>, _>>()?;
+
+    let mut table = Vec::new();
+    table.push(args.len());
+    table.extend(argv);
+    table.push(0);
+    table.extend(envp);
+    table.push(0);
+    for &(key, value) in auxv {
+        table.push(key);
+        table.push(value);
+    }
+    table.push(AT_NULL);
+    table.push(0);
+
+    // The stack pointer is 16 bytes aligned in the RISC-V calling convention
+    let sp = sp
+        .checked_sub(table.len() * size_of::<usize>())
+        .ok_or("Arguments out of the stack")?
+        & !0xf;
+    for (i, value) in table.iter().enumerate() {
+        vm.write(sp + i * size_of::<usize>(), &value.to_ne_bytes())?;
+    }
+    Ok(sp)
+}
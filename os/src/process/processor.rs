@@ -2,19 +2,22 @@ use crate::interrupt::{disable_and_store, enable_and_wfi, restore};
 use crate::process::pool::ThreadPool;
 use crate::process::thread::{Thread, ThreadStatus};
 use crate::process::{ExitCode, ThreadID};
+use crate::sync::spin_no_irq::SpinNoIrqLock;
 use alloc::boxed::Box;
 use core::cell::UnsafeCell;
 
 // Processor Status
 pub struct Status {
-    pool: Box<ThreadPool>,
+    // Shared by the processors of all the harts
+    pool: &'static SpinNoIrqLock<ThreadPool>,
     idle: Box<Thread>,
     current: Option<(ThreadID, Box<Thread>)>,
 }
 
 // Why there is a 'UnsafeCell' wrapper? Rust makes is effort to ensure the safety of multi-threads-accessing
 // We can simply add this wrapper to disable the check.
-// Why not mutex? Because we can assert that only one can access this function.
+// Why not mutex? Every hart has its own processor, only that hart accesses it (with interrupts disabled).
+// Note that a thread may be resumed on another hart after switching, so never use 'self' after that.
 pub struct Processor {
     status: UnsafeCell<Option<Status>>,
 }
@@ -28,7 +31,7 @@ impl Processor {
         }
     }
 
-    pub fn initialize(&self, idle: Box<Thread>, pool: Box<ThreadPool>) {
+    pub fn initialize(&self, idle: Box<Thread>, pool: &'static SpinNoIrqLock<ThreadPool>) {
         unsafe {
             *self.status.get() = Some(Status {
                 pool,
@@ -42,20 +45,27 @@ impl Processor {
         Thread::boot().switch_to(&mut self.status().idle);
     }
 
-    pub fn add_thread(&self, thread: Box<Thread>) {
-        self.status().pool.add(thread);
+    // Parent is the one who can wait for the thread, None if the pool is full
+    pub fn add_thread(&self, thread: Box<Thread>, parent: Option<ThreadID>) -> Option<ThreadID> {
+        // A rejected thread is dropped here, its process may close files
+        self.with_pool(|pool| pool.add(thread, parent)).ok()
     }
 
     fn status(&self) -> &mut Status {
         unsafe { &mut *self.status.get() }.as_mut().unwrap()
     }
 
+    // Interrupts are disabled while holding the pool, or the timer may lock it again on this hart
+    fn with_pool<T>(&self, f: impl FnOnce(&mut ThreadPool) -> T) -> T {
+        f(&mut self.status().pool.lock())
+    }
+
     pub fn idle_main(&self) -> ! {
         let status = self.status();
         disable_and_store();
 
         loop {
-            if let Some(thread) = status.pool.acquire() {
+            if let Some(thread) = self.with_pool(|pool| pool.acquire()) {
                 // Switch to the acquired one
                 status.current = Some(thread);
                 status
@@ -64,7 +74,8 @@ impl Processor {
 
                 // Switch back
                 let (id, thread) = status.current.take().unwrap();
-                status.pool.retrieve(id, thread);
+                let exited = self.with_pool(|pool| pool.retrieve(id, thread));
+                drop(exited);
             } else {
                 // Wait for next interrupt
                 enable_and_wfi();
@@ -76,23 +87,28 @@ impl Processor {
         }
     }
 
+    // Back to idle, the current thread may continue on any hart later
+    fn switch_to_idle(&self) {
+        let status = self.status();
+        status
+            .current
+            .as_mut()
+            .unwrap()
+            .1
+            .switch_to(&mut status.idle);
+    }
+
     // Where could this function be executed?
     // I may say that not a specific thread but from interrupt
     pub fn tick(&self) {
-        let status = self.status();
-        if !status.current.is_none() {
+        if let Some((id, _)) = self.status().current {
             // One is running
-            if status.pool.tick() {
+            if self.with_pool(|pool| pool.tick(id)) {
                 // We need a change
                 let flags = disable_and_store();
 
                 // Switch to idle for next scheduling
-                status
-                    .current
-                    .as_mut()
-                    .unwrap()
-                    .1
-                    .switch_to(&mut status.idle);
+                self.switch_to_idle();
 
                 // Restore interrupt
                 restore(flags);
@@ -100,57 +116,93 @@ impl Processor {
         } // Else for continuing idle thread (back to idle from interrupt)
     }
 
-    pub fn sleep(&self) {
-        let status = self.status();
-        if !status.current.is_none() {
-            // Disable async interrupt and switch to idle (will enable again)
-            let flags = disable_and_store();
-            let tid = status.current.as_mut().unwrap().0;
-            let info = status.pool.threads[tid].as_mut().unwrap();
-            info.status = ThreadStatus::Sleeping;
-            status
-                .current
-                .as_mut()
-                .unwrap()
-                .1
-                .switch_to(&mut *status.idle);
-
-            // Switch back and restore
-            restore(flags);
-        }
+    // The thread is marked sleeping before 'prepare' lets others know how to wake it up,
+    // so a wake up coming at once (maybe from another hart) is never lost
+    // Return false without calling 'prepare' if there is no current thread to sleep
+    pub fn sleep_with(&self, prepare: impl FnOnce(ThreadID)) -> bool {
+        let id = match self.status().current {
+            Some((id, _)) => id,
+            None => return false,
+        };
+        // Disable async interrupt and switch to idle (will enable again)
+        let flags = disable_and_store();
+        self.with_pool(|pool| {
+            pool.threads[id].as_mut().unwrap().status = ThreadStatus::Sleeping;
+        });
+        prepare(id);
+        self.switch_to_idle();
+
+        // Switch back and restore
+        restore(flags);
+        true
     }
 
     pub fn wake_up(&self, id: ThreadID) {
-        let status = self.status();
-        status.pool.wake_up(id);
+        self.with_pool(|pool| pool.wake_up(id));
+    }
+
+    pub fn set_priority(&self, priority: usize) {
+        let id = self.current_tid();
+        self.with_pool(|pool| pool.set_priority(id, priority));
     }
 
     pub fn current_tid(&self) -> usize {
         self.status().current.as_mut().unwrap().0 as usize
     }
 
-    pub fn exit(&self, _code: ExitCode) -> ! {
+    pub fn current_thread(&self) -> &'static mut Thread {
+        let thread = &mut *self.status().current.as_mut().unwrap().1;
+        // The box stays in place till the thread exits
+        unsafe { &mut *(thread as *mut Thread) }
+    }
+
+    // Let the address space of the running thread fix the fault
+    pub fn handle_page_fault(&self, vaddr: usize) -> bool {
+        match self.status().current.as_mut() {
+            Some((_, thread)) => match &thread.process {
+                Some(process) => process.lock().vm.handle_page_fault(vaddr),
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    // Reap an exited child (any if target is None), Err if there is no such child
+    // Ok(None) means we slept till a child exited, try again then
+    pub fn wait(&self, target: Option<ThreadID>) -> Result<Option<(ThreadID, ExitCode)>, ()> {
+        let flags = disable_and_store();
+        let id = self.current_tid();
+
+        // Fall asleep with the pool locked, or the exiting child may miss us
+        let result = self.with_pool(|pool| {
+            let result = pool.wait(id, target);
+            if let Ok(None) = result {
+                let info = pool.threads[id].as_mut().unwrap();
+                info.waiting = true;
+                info.status = ThreadStatus::Sleeping;
+            }
+            result
+        });
+        if let Ok(None) = result {
+            self.switch_to_idle();
+        }
+
+        restore(flags);
+        result
+    }
+
+    pub fn exit(&self, code: ExitCode) -> ! {
         // Disable interrupt
         disable_and_store();
 
         // Get id
-        let status = self.status();
-        let id = status.current.as_ref().unwrap().0;
-
-        // Exit and switch to idle
-        status.pool.exit(id);
+        let id = self.current_tid();
 
-        // Some thread is waiting
-        if let Some(wait) = status.current.as_ref().unwrap().1.wait {
-            status.pool.wake_up(wait);
-        }
+        // Exit (become a zombie if someone may wait for it), the parent is woken up after retrieving
+        self.with_pool(|pool| pool.exit(id, code));
 
-        status
-            .current
-            .as_mut()
-            .unwrap()
-            .1
-            .switch_to(&mut status.idle);
+        // Switch to idle
+        self.switch_to_idle();
 
         loop {}
     }
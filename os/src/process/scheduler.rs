@@ -1,5 +1,5 @@
 use crate::process::ThreadID;
-use alloc::vec::Vec;
+use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
 
 pub trait Scheduler {
     // Add to the waiting list
@@ -8,11 +8,24 @@ pub trait Scheduler {
     // Give me a thread to run from the available ones
     fn pop(&mut self) -> Option<ThreadID>;
 
-    // Timer tick
-    fn tick(&mut self) -> bool;
+    // Timer tick of a running thread, return whether it should give up the hart
+    fn tick(&mut self, current: ThreadID) -> bool;
 
     // A thread is exiting
     fn exit(&mut self, id: ThreadID);
+
+    // Larger for more time, ignored by the schedulers without priorities
+    fn set_priority(&mut self, _id: ThreadID, _priority: usize) {}
+}
+
+// Choose a scheduler by its name ("rr", "stride" or "mlfq")
+pub fn from_name(name: &str, max_time: usize) -> Option<Box<dyn Scheduler>> {
+    match name {
+        "rr" => Some(Box::new(RoundRobinScheduler::new(max_time))),
+        "stride" => Some(Box::new(StrideScheduler::new(max_time))),
+        "mlfq" => Some(Box::new(MultiLevelFeedbackQueue::new(max_time))),
+        _ => None,
+    }
 }
 
 // Round Robin Scheduler
@@ -27,7 +40,6 @@ struct RoundRobinInfo {
 pub struct RoundRobinScheduler {
     threads: Vec<RoundRobinInfo>,
     max_time: usize,
-    current: usize,
 }
 
 impl RoundRobinScheduler {
@@ -35,7 +47,6 @@ impl RoundRobinScheduler {
         let mut instance = RoundRobinScheduler {
             threads: Vec::default(),
             max_time,
-            current: 0,
         };
         instance.threads.push(RoundRobinInfo::default());
         instance
@@ -71,26 +82,203 @@ impl Scheduler for RoundRobinScheduler {
             self.threads[ret].prev = 0;
             self.threads[ret].next = 0;
             self.threads[ret].valid = false;
-            self.current = ret;
             Some(ret - 1)
         } else {
             None
         }
     }
 
-    fn tick(&mut self) -> bool {
-        let id = self.current;
-        if id != 0 {
+    fn tick(&mut self, current: ThreadID) -> bool {
+        let id = current + 1;
+        if id < self.threads.len() && self.threads[id].time > 0 {
             self.threads[id].time -= 1;
             return self.threads[id].time == 0;
         }
         return true;
     }
 
+    // The id may be reused, the next one starts with a full time slice
     fn exit(&mut self, id: ThreadID) {
         let id = id + 1;
-        if self.current == id {
-            self.current = 0;
+        if id < self.threads.len() {
+            self.threads[id].time = 0;
+        }
+    }
+}
+
+// Stride Scheduler
+// The ready one with the least pass runs, then its pass grows by BIG_STRIDE / priority
+const BIG_STRIDE: usize = 1 << 20;
+pub const DEFAULT_PRIORITY: usize = 16;
+// Far below BIG_STRIDE, so every pass grows by a stride of at least 1024
+pub const MAX_PRIORITY: usize = 1 << 10;
+
+#[derive(Default)]
+struct StrideInfo {
+    valid: bool,
+    ready: bool,
+    priority: usize,
+    pass: usize,
+    time: usize,
+}
+
+pub struct StrideScheduler {
+    threads: Vec<StrideInfo>,
+    max_time: usize,
+    // Pass of the latest chosen one
+    pass: usize,
+}
+
+impl StrideScheduler {
+    pub fn new(max_time: usize) -> Self {
+        StrideScheduler {
+            threads: Vec::default(),
+            max_time,
+            pass: 0,
+        }
+    }
+}
+
+impl Scheduler for StrideScheduler {
+    fn push(&mut self, id: ThreadID) {
+        if id + 1 > self.threads.len() {
+            self.threads.resize_with(id + 1, Default::default);
+        }
+
+        let info = &mut self.threads[id];
+        if !info.valid {
+            info.valid = true;
+            info.priority = DEFAULT_PRIORITY;
+        }
+        // Do not let a thread make up for all the time it slept
+        if info.pass < self.pass {
+            info.pass = self.pass;
+        }
+        if info.time == 0 {
+            info.time = self.max_time;
+        }
+        info.ready = true;
+    }
+
+    fn pop(&mut self) -> Option<ThreadID> {
+        let (id, info) = self
+            .threads
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, info)| info.ready)
+            .min_by_key(|(_, info)| info.pass)?;
+        info.ready = false;
+        self.pass = info.pass;
+        info.pass += BIG_STRIDE / info.priority;
+        Some(id)
+    }
+
+    fn tick(&mut self, current: ThreadID) -> bool {
+        if current < self.threads.len() && self.threads[current].time > 0 {
+            self.threads[current].time -= 1;
+            return self.threads[current].time == 0;
+        }
+        return true;
+    }
+
+    fn exit(&mut self, id: ThreadID) {
+        if id < self.threads.len() {
+            self.threads[id] = StrideInfo::default();
+        }
+    }
+
+    fn set_priority(&mut self, id: ThreadID, priority: usize) {
+        if id < self.threads.len() {
+            self.threads[id].priority = priority.max(1).min(MAX_PRIORITY);
+        }
+    }
+}
+
+// Multi-level Feedback Queue
+// A thread using up its time slice goes down a level (with a longer slice),
+// and all of them are boosted to the top from time to time, so nobody starves
+const MLFQ_LEVELS: usize = 3;
+const MLFQ_BOOST_TICKS: usize = 50;
+
+#[derive(Default)]
+struct MlfqInfo {
+    level: usize,
+    time: usize,
+    used_up: bool,
+}
+
+pub struct MultiLevelFeedbackQueue {
+    queues: Vec<VecDeque<ThreadID>>,
+    threads: Vec<MlfqInfo>,
+    max_time: usize,
+    ticks: usize,
+}
+
+impl MultiLevelFeedbackQueue {
+    pub fn new(max_time: usize) -> Self {
+        let mut queues = Vec::new();
+        queues.resize_with(MLFQ_LEVELS, VecDeque::new);
+        MultiLevelFeedbackQueue {
+            queues,
+            threads: Vec::default(),
+            max_time,
+            ticks: 0,
+        }
+    }
+
+    fn boost(&mut self) {
+        for info in self.threads.iter_mut() {
+            info.level = 0;
+        }
+        for level in 1..MLFQ_LEVELS {
+            while let Some(id) = self.queues[level].pop_front() {
+                self.queues[0].push_back(id);
+            }
+        }
+    }
+}
+
+impl Scheduler for MultiLevelFeedbackQueue {
+    fn push(&mut self, id: ThreadID) {
+        if id + 1 > self.threads.len() {
+            self.threads.resize_with(id + 1, Default::default);
+        }
+
+        let info = &mut self.threads[id];
+        if info.used_up {
+            info.used_up = false;
+            if info.level + 1 < MLFQ_LEVELS {
+                info.level += 1;
+            }
+        }
+        if info.time == 0 {
+            info.time = self.max_time << info.level;
+        }
+        self.queues[info.level].push_back(id);
+    }
+
+    fn pop(&mut self) -> Option<ThreadID> {
+        self.queues.iter_mut().find_map(|queue| queue.pop_front())
+    }
+
+    fn tick(&mut self, current: ThreadID) -> bool {
+        self.ticks += 1;
+        if self.ticks % MLFQ_BOOST_TICKS == 0 {
+            self.boost();
+        }
+
+        if current < self.threads.len() && self.threads[current].time > 0 {
+            let info = &mut self.threads[current];
+            info.time -= 1;
+            info.used_up = info.time == 0;
+            return info.used_up;
+        }
+        return true;
+    }
+
+    fn exit(&mut self, id: ThreadID) {
+        if id < self.threads.len() {
+            self.threads[id] = MlfqInfo::default();
         }
     }
 }
@@ -1,15 +1,45 @@
-use crate::alloc::alloc::{alloc, dealloc, Layout};
-use crate::consts::KERNEL_STACK_SIZE;
+use crate::consts::{
+    KERNEL_STACK_REGION, KERNEL_STACK_REGION_SIZE, KERNEL_STACK_SIZE, KERNEL_STACK_SLOT, PAGE_SIZE,
+};
+use crate::memory::{kernel_map, kernel_unmap};
+use crate::sync::spin_no_irq::SpinNoIrqLock;
+use alloc::{vec, vec::Vec};
+use lazy_static::*;
+
+const SLOT_COUNT: usize = KERNEL_STACK_REGION_SIZE / KERNEL_STACK_SLOT;
+
+lazy_static! {
+    // Set by 'make kernel_stack=<KB>', at most half a slot without the guard page
+    // (trap.asm takes the upper half as unmapped)
+    static ref STACK_SIZE: usize = option_env!("KERNEL_STACK_KB")
+        .and_then(|kb| kb.parse::<usize>().ok())
+        .map_or(KERNEL_STACK_SIZE, |kb| kb * 1024)
+        .max(PAGE_SIZE)
+        .min(KERNEL_STACK_SLOT / 2 - PAGE_SIZE)
+        / PAGE_SIZE
+        * PAGE_SIZE;
+    static ref SLOTS_USED: SpinNoIrqLock<Vec<bool>> = SpinNoIrqLock::new(vec![false; SLOT_COUNT]);
+}
 
 // The usize var will be bottom
+// A slot is [guard page, stack, unmapped till the next slot]
 pub struct KernelStack(usize);
 
 impl KernelStack {
-    pub fn new() -> Self {
-        let bottom = unsafe {
-            alloc(Layout::from_size_align(KERNEL_STACK_SIZE, KERNEL_STACK_SIZE).unwrap()) as usize
+    // None if all the slots are used or there is no memory for the stack
+    pub fn new() -> Option<Self> {
+        let slot = {
+            let mut used = SLOTS_USED.lock();
+            let slot = used.iter().position(|&used| !used)?;
+            used[slot] = true;
+            slot
         };
-        KernelStack(bottom)
+        let bottom = KERNEL_STACK_REGION + slot * KERNEL_STACK_SLOT + PAGE_SIZE;
+        if !kernel_map(bottom, bottom + *STACK_SIZE) {
+            SLOTS_USED.lock()[slot] = false;
+            return None;
+        }
+        Some(KernelStack(bottom))
     }
 
     // Why empty? Just for boot thread, we already have stack
@@ -17,18 +47,27 @@ impl KernelStack {
         KernelStack(0)
     }
 
+    // The highest 16 bytes keep the hart id while the thread is in U mode (see trap.asm)
     pub fn top(&self) -> usize {
-        self.0 + KERNEL_STACK_SIZE
+        self.0 + *STACK_SIZE - 16
     }
 }
 
 impl Drop for KernelStack {
     fn drop(&mut self) {
-        unsafe {
-            dealloc(
-                self.0 as _,
-                Layout::from_size_align(KERNEL_STACK_SIZE, KERNEL_STACK_SIZE).unwrap(),
-            );
+        if self.0 == 0 {
+            return;
         }
+        kernel_unmap(self.0, self.0 + *STACK_SIZE);
+        SLOTS_USED.lock()[(self.0 - KERNEL_STACK_REGION) / KERNEL_STACK_SLOT] = false;
+    }
+}
+
+// Whether vaddr is in the region but not in a stack (a kernel stack overflowed if it's accessed)
+pub fn is_guard(vaddr: usize) -> bool {
+    if vaddr < KERNEL_STACK_REGION || vaddr - KERNEL_STACK_REGION >= KERNEL_STACK_REGION_SIZE {
+        return false;
     }
+    let offset = (vaddr - KERNEL_STACK_REGION) % KERNEL_STACK_SLOT;
+    offset < PAGE_SIZE || offset >= PAGE_SIZE + *STACK_SIZE
 }
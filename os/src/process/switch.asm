@@ -1,5 +1,5 @@
 // Note this file is a function
-// pub unsafe extern "C" fn switch(&mut self, _target: &mut Context)
+// pub unsafe extern "C" fn switch(&mut self, _target: &mut Context, _flush: bool)
 
 .equ WSIZE, 8 // Word size
 
@@ -41,7 +41,10 @@
     // Restore
     LOAD s11, 1
     csrw satp, s11  // Page table address switch
-    sfence.vma      // TLB Refresh
+    // TLB refresh only if refresh_token asked (a2): the ASID may have entries of another page table
+    beqz a2, 1f
+    sfence.vma
+1:
     LOAD ra, 0      // ra = __trap_ret (just for ch6), ret instruction will let pc jump there
     LOAD s0, 2
     LOAD s1, 3
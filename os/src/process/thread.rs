@@ -1,85 +1,90 @@
-use crate::consts::{USER_STACK_OFFSET, USER_STACK_SIZE};
-use crate::memory::manager::attr::MemoryAttr;
-use crate::memory::manager::handler::ByFrame;
 use crate::process::context::Context;
-use crate::process::elf::ElfExt;
+use crate::process::process::Process;
 use crate::process::stack::KernelStack;
 use crate::process::{ExitCode, ThreadID};
-use alloc::boxed::Box;
+use crate::trap::frame::TrapFrame;
+use alloc::{boxed::Box, string::String, sync::Arc};
 use riscv::register::satp;
-use xmas_elf::{header, ElfFile};
+use spin::Mutex;
 
 pub struct Thread {
     pub context: Context,
     pub stack: KernelStack,
-    pub wait: Option<ThreadID>,
+    // None for kernel threads
+    pub process: Option<Arc<Mutex<Process>>>,
 }
 
 impl Thread {
     // The function is implemented by switching the context
     pub fn switch_to(&mut self, target: &mut Thread) {
         unsafe {
-            self.context.switch(&mut target.context);
+            let flush = target.context.refresh_token();
+            self.context.switch(&mut target.context, flush);
         }
     }
 
-    // New kernel thread (S mode)
+    // New kernel thread (S mode), only created at boot
     pub fn new_kernel(entry: usize) -> Box<Thread> {
         unsafe {
-            let stack = KernelStack::new();
+            let stack = KernelStack::new().expect("No kernel stack for a kernel thread");
             Box::new(Thread {
                 context: Context::new_kernel(entry, stack.top(), satp::read().bits()),
                 stack,
-                wait: None,
+                process: None,
             })
         }
     }
 
-    pub fn new_user(data: &[u8], wait_thread: Option<ThreadID>) -> Box<Thread> {
-        let elf = ElfFile::new(data).unwrap();
-
-        match elf.header.pt2.type_().as_type() {
-            header::Type::Executable => {}
-            header::Type::SharedObject => {
-                panic!("Shared object is not supported.");
-            }
-            _ => {
-                panic!("Unsupported ELF type.");
-            }
-        }
-
-        let entry = elf.header.pt2.entry_point() as usize;
-        // The manager will add other areas into it
-        let mut manager = elf.new_manager();
-
-        let user_stack = {
-            // User stack will be in a fixed space of kernel
-            let (bottom, top) = (USER_STACK_OFFSET, USER_STACK_OFFSET + USER_STACK_SIZE);
-            manager.push(
-                bottom,
-                top,
-                MemoryAttr::new().set_user(),
-                ByFrame::new(),
-                None,
-            );
-            top
-        };
-
-        let kernel_stack = KernelStack::new();
-        Box::new(Thread {
+    // main(argc, argv) of the program will get the arguments
+    pub fn new_user(
+        data: &[u8],
+        args: &[String],
+        envs: &[String],
+        parent: Option<ThreadID>,
+    ) -> Result<Box<Thread>, &'static str> {
+        let kernel_stack = KernelStack::new().ok_or("Out of kernel stacks")?;
+        let (process, info) = Process::new_user(data, args, envs, parent)?;
+        let thread = Box::new(Thread {
             context: unsafe {
-                Context::new_user(entry, user_stack, kernel_stack.top(), manager.token())
+                Context::new_user(info.entry, info.sp, kernel_stack.top(), process.vm.token())
             },
             stack: kernel_stack,
-            wait: wait_thread,
-        })
+            process: Some(Arc::new(Mutex::new(process))),
+        });
+        thread.append_args([info.argc, info.argv, 0]);
+        Ok(thread)
+    }
+
+    // Duplicate a user thread with its process, the child will return 0 from the syscall
+    // None if there is no kernel stack for it
+    pub fn fork(&self, frame: &TrapFrame, parent: ThreadID) -> Option<Box<Thread>> {
+        let kernel_stack = KernelStack::new()?;
+        let process = self.process.as_ref().unwrap().lock().fork(parent);
+        Some(Box::new(Thread {
+            context: unsafe { Context::new_fork(frame, kernel_stack.top(), process.vm.token()) },
+            stack: kernel_stack,
+            process: Some(Arc::new(Mutex::new(process))),
+        }))
+    }
+
+    // A new thread in the same process, running on the given user stack
+    // None if there is no kernel stack for it
+    pub fn clone_thread(&self, frame: &TrapFrame, user_stack: usize) -> Option<Box<Thread>> {
+        let kernel_stack = KernelStack::new()?;
+        let process = self.process.clone().unwrap();
+        let token = process.lock().vm.token();
+        Some(Box::new(Thread {
+            context: unsafe { Context::new_clone(frame, user_stack, kernel_stack.top(), token) },
+            stack: kernel_stack,
+            process: Some(process),
+        }))
     }
 
     pub fn boot() -> Box<Thread> {
         Box::new(Thread {
             context: Context::null(),
             stack: KernelStack::new_empty(),
-            wait: None,
+            process: None,
         })
     }
 
@@ -92,15 +97,20 @@ impl Thread {
 
 // TODO: fill up the info
 #[derive(Clone)]
-#[allow(dead_code)]
 pub enum ThreadStatus {
     Ready,
     Running(ThreadID),
     Sleeping,
     Exited(ExitCode),
+    // Exited and retrieved, waiting for the parent to reap it
+    Zombie(ExitCode),
 }
 
 pub struct ThreadInfo {
     pub status: ThreadStatus,
     pub thread: Option<Box<Thread>>,
+    // Only this one can wait for the thread
+    pub parent: Option<ThreadID>,
+    // Sleeping in wait4 for a child to exit
+    pub waiting: bool,
 }
@@ -11,6 +11,10 @@ const SBI_REMOTE_SFENCE_VMA: usize = 6;
 const SBI_REMOTE_SFENCE_VMA_ASID: usize = 7;
 const SBI_SHUTDOWN: usize = 8;
 
+// Hart state management extension (SBI v0.2)
+const SBI_EXT_HSM: usize = 0x48534D;
+const SBI_HSM_HART_START: usize = 0;
+
 #[inline(always)]
 fn sbi_call(which: usize, arg_0: usize, arg_1: usize, arg_2: usize) -> usize {
     let ret;
@@ -24,6 +28,21 @@ fn sbi_call(which: usize, arg_0: usize, arg_1: usize, arg_2: usize) -> usize {
     ret
 }
 
+// SBI v0.2 calls take the extension id in a7 and the function id in a6, a0 is the error
+#[inline(always)]
+fn sbi_call_ext(ext: usize, fid: usize, arg_0: usize, arg_1: usize, arg_2: usize) -> isize {
+    let error;
+    let _value: usize;
+    unsafe {
+        asm!("ecall"
+            : "={x10}" (error), "={x11}" (_value)
+            : "{x10}" (arg_0), "{x11}" (arg_1), "{x12}" (arg_2), "{x16}" (fid), "{x17}" (ext)
+            : "memory"
+            : "volatile");
+    }
+    error
+}
+
 pub fn console_putchar(ch: usize) {
     sbi_call(SBI_CONSOLE_PUTCHAR, ch, 0, 0);
 }
@@ -39,3 +58,19 @@ pub fn set_timer(time: u64) {
     #[cfg(target_pointer_width = "64")]
     sbi_call(SBI_SET_TIMER, time as usize, 0, 0);
 }
+
+// Flush [start, start + size) from the TLBs of the harts in the mask (all ASIDs)
+pub fn remote_sfence_vma(hart_mask: usize, start: usize, size: usize) {
+    // The legacy call takes the address of the mask
+    sbi_call(
+        SBI_REMOTE_SFENCE_VMA,
+        &hart_mask as *const usize as usize,
+        start,
+        size,
+    );
+}
+
+// Start a stopped hart at the physical address with a0 = hart id, a1 = opaque
+pub fn hart_start(hart_id: usize, start_addr: usize, opaque: usize) -> isize {
+    sbi_call_ext(SBI_EXT_HSM, SBI_HSM_HART_START, hart_id, start_addr, opaque)
+}
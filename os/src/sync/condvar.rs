@@ -1,11 +1,19 @@
-use crate::process::{current_tid, sleep, wake_up, ThreadID};
+use crate::process::{sleep_with, wake_up, ThreadID};
+use crate::sync::mutex::MutexGuard;
+use crate::sync::spin_no_irq::{SpinNoIrqGuard, SpinNoIrqLock};
 use alloc::collections::VecDeque;
-use spin::Mutex;
 
 // Condvar for 'condition var'
-#[derive(Default)]
 pub struct Condvar {
-    queue: Mutex<VecDeque<ThreadID>>,
+    queue: SpinNoIrqLock<VecDeque<ThreadID>>,
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Condvar {
+            queue: SpinNoIrqLock::new(VecDeque::new()),
+        }
+    }
 }
 
 impl Condvar {
@@ -13,17 +21,56 @@ impl Condvar {
         Condvar::default()
     }
 
-    // Wait till some condition
-    pub fn wait(&self) {
-        self.queue.lock().push_back(current_tid());
-        sleep();
+    // Release the lock and wait till some condition, then lock it again
+    // We're in the queue before the lock is released, so no notify after that is missed
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        self.sleep_and(|| drop(guard));
+        mutex.lock()
+    }
+
+    // Same as wait, but for the spin locks (interrupts stay disabled till we're asleep)
+    pub fn wait_spin<'a, T>(&self, guard: SpinNoIrqGuard<'a, T>) -> SpinNoIrqGuard<'a, T> {
+        let mut released = None;
+        self.sleep_and(|| released = Some(guard.unlock_irq_off()));
+        let (lock, flags) = released.unwrap();
+        lock.relock(flags)
+    }
+
+    // The thread is already sleeping (not yet switched out) when it's put into the queue
+    // Without a thread (booting or idle) nothing can sleep, the caller checks again at once
+    fn sleep_and(&self, release: impl FnOnce()) {
+        let mut release = Some(release);
+        let slept = sleep_with(|id| {
+            self.queue.lock().push_back(id);
+            release.take().unwrap()();
+        });
+        if !slept {
+            release.take().unwrap()();
+        }
     }
 
     // The condition is satisfied
-    // Pop the first thread
-    pub fn notify(&self) {
+    // Pop the first thread, return false if nobody is waiting
+    pub fn notify(&self) -> bool {
         let id = self.queue.lock().pop_front();
-        if let Some(id) = id {
+        match id {
+            Some(id) => {
+                wake_up(id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Nobody is waiting
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().is_empty()
+    }
+
+    pub fn notify_all(&self) {
+        let ids: VecDeque<ThreadID> = self.queue.lock().drain(..).collect();
+        for id in ids {
             wake_up(id);
         }
     }
@@ -0,0 +1,48 @@
+use crate::errno::Errno;
+use crate::sync::condvar::Condvar;
+use crate::sync::mutex::Mutex;
+use alloc::{collections::BTreeMap, sync::Arc};
+use lazy_static::*;
+
+// (id of the address space, user virtual address)
+pub type FutexKey = (usize, usize);
+
+// Wait queues of the futexes having waiters, only used by the syscalls so the lock may sleep
+lazy_static! {
+    static ref QUEUES: Mutex<BTreeMap<FutexKey, Arc<Condvar>>> = Mutex::new(BTreeMap::new());
+}
+
+// Sleep if the value load() copies from the futex is still val, EAGAIN if not
+pub fn wait(
+    key: FutexKey,
+    load: impl FnOnce() -> Result<u32, Errno>,
+    val: u32,
+) -> Result<(), Errno> {
+    let mut queues = QUEUES.lock();
+    // Compared with the queues locked, so whoever changes it and wakes us later finds us
+    if load()? != val {
+        return Err(Errno::EAGAIN);
+    }
+    let queue = queues
+        .entry(key)
+        .or_insert_with(|| Arc::new(Condvar::new()))
+        .clone();
+    drop(queue.wait(queues));
+    Ok(())
+}
+
+// Wake up at most count threads, return how many are woken up
+pub fn wake(key: FutexKey, count: usize) -> usize {
+    let mut queues = QUEUES.lock();
+    let mut woken = 0;
+    if let Some(queue) = queues.get(&key) {
+        while woken < count && queue.notify() {
+            woken += 1;
+        }
+        // Nobody is waiting now
+        if queue.is_empty() {
+            queues.remove(&key);
+        }
+    }
+    woken
+}
@@ -1 +1,6 @@
 pub mod condvar;
+pub mod futex;
+pub mod mutex;
+pub mod rwlock;
+pub mod semaphore;
+pub mod spin_no_irq;
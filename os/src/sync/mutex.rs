@@ -0,0 +1,65 @@
+use crate::sync::condvar::Condvar;
+use crate::sync::spin_no_irq::SpinNoIrqLock;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+// Threads waiting for the lock sleep instead of spinning (do not use it in interrupt handlers)
+pub struct Mutex<T> {
+    locked: SpinNoIrqLock<bool>,
+    waiters: Condvar,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub fn new(data: T) -> Self {
+        Mutex {
+            locked: SpinNoIrqLock::new(false),
+            waiters: Condvar::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<T> {
+        let mut locked = self.locked.lock();
+        while *locked {
+            locked = self.waiters.wait_spin(locked);
+        }
+        *locked = true;
+        MutexGuard { mutex: self }
+    }
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    // For Condvar to lock it again
+    pub fn mutex(&self) -> &'a Mutex<T> {
+        self.mutex
+    }
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        *self.mutex.locked.lock() = false;
+        self.mutex.waiters.notify();
+    }
+}
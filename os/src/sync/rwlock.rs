@@ -0,0 +1,102 @@
+use crate::sync::condvar::Condvar;
+use crate::sync::spin_no_irq::SpinNoIrqLock;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+#[derive(Default)]
+struct RwState {
+    readers: usize,
+    writer: bool,
+    // New readers wait for them, so writers never starve
+    waiting_writers: usize,
+}
+
+// Many readers or one writer, the others sleep
+pub struct RwLock<T> {
+    state: SpinNoIrqLock<RwState>,
+    changed: Condvar,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub fn new(data: T) -> Self {
+        RwLock {
+            state: SpinNoIrqLock::new(RwState::default()),
+            changed: Condvar::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        let mut state = self.state.lock();
+        while state.writer || state.waiting_writers > 0 {
+            state = self.changed.wait_spin(state);
+        }
+        state.readers += 1;
+        RwLockReadGuard { lock: self }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        let mut state = self.state.lock();
+        state.waiting_writers += 1;
+        while state.writer || state.readers > 0 {
+            state = self.changed.wait_spin(state);
+        }
+        state.waiting_writers -= 1;
+        state.writer = true;
+        RwLockWriteGuard { lock: self }
+    }
+}
+
+impl<'a, T> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        let last = {
+            let mut state = self.lock.state.lock();
+            state.readers -= 1;
+            state.readers == 0
+        };
+        if last {
+            self.lock.changed.notify_all();
+        }
+    }
+}
+
+impl<'a, T> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.state.lock().writer = false;
+        self.lock.changed.notify_all();
+    }
+}
@@ -0,0 +1,42 @@
+use crate::sync::condvar::Condvar;
+use crate::sync::spin_no_irq::SpinNoIrqLock;
+
+// Counting semaphore, down sleeps while the count is 0
+pub struct Semaphore {
+    count: SpinNoIrqLock<usize>,
+    waiters: Condvar,
+}
+
+impl Semaphore {
+    pub fn new(count: usize) -> Self {
+        Semaphore {
+            count: SpinNoIrqLock::new(count),
+            waiters: Condvar::new(),
+        }
+    }
+
+    // P
+    pub fn down(&self) {
+        let mut count = self.count.lock();
+        while *count == 0 {
+            count = self.waiters.wait_spin(count);
+        }
+        *count -= 1;
+    }
+
+    // P without waiting, false if the count is 0
+    pub fn try_down(&self) -> bool {
+        let mut count = self.count.lock();
+        if *count == 0 {
+            return false;
+        }
+        *count -= 1;
+        true
+    }
+
+    // V
+    pub fn up(&self) {
+        *self.count.lock() += 1;
+        self.waiters.notify();
+    }
+}
@@ -0,0 +1,74 @@
+use crate::interrupt::{disable_and_store, restore};
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use spin::{Mutex, MutexGuard};
+
+// Spin lock holding with interrupts disabled, so the holder is never preempted on its hart
+// and no interrupt handler spins on it there (for short sections, may be used in handlers)
+pub struct SpinNoIrqLock<T> {
+    inner: Mutex<T>,
+}
+
+pub struct SpinNoIrqGuard<'a, T> {
+    lock: &'a SpinNoIrqLock<T>,
+    guard: ManuallyDrop<MutexGuard<'a, T>>,
+    // Interrupt flags before locking
+    flags: usize,
+}
+
+impl<T> SpinNoIrqLock<T> {
+    pub const fn new(data: T) -> Self {
+        SpinNoIrqLock {
+            inner: Mutex::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> SpinNoIrqGuard<T> {
+        let flags = disable_and_store();
+        self.relock(flags)
+    }
+
+    // Lock again after unlock_irq_off, the flags are restored when the guard is dropped
+    pub fn relock(&self, flags: usize) -> SpinNoIrqGuard<T> {
+        disable_and_store();
+        SpinNoIrqGuard {
+            lock: self,
+            guard: ManuallyDrop::new(self.inner.lock()),
+            flags,
+        }
+    }
+}
+
+impl<'a, T> SpinNoIrqGuard<'a, T> {
+    // Unlock but keep interrupts disabled (for Condvar), return the lock and the old flags
+    pub fn unlock_irq_off(self) -> (&'a SpinNoIrqLock<T>, usize) {
+        let mut this = ManuallyDrop::new(self);
+        unsafe {
+            ManuallyDrop::drop(&mut this.guard);
+        }
+        (this.lock, this.flags)
+    }
+}
+
+impl<'a, T> Deref for SpinNoIrqGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &*self.guard
+    }
+}
+
+impl<'a, T> DerefMut for SpinNoIrqGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut *self.guard
+    }
+}
+
+impl<'a, T> Drop for SpinNoIrqGuard<'a, T> {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.guard);
+        }
+        restore(self.flags);
+    }
+}
@@ -1,44 +1,888 @@
+use crate::consts::{
+    ARG_MAX, PAGE_SIZE, PATH_MAX, USER_MMAP_BASE, USER_SPACE_END, USER_STACK_OFFSET,
+    USER_STACK_SIZE,
+};
+use crate::errno::Errno;
+use crate::fs::file::{File, MAX_FDS, SEEK_CUR, SEEK_SET};
+use crate::fs::pipe;
+use crate::fs::{INodeExt, ROOT_INODE};
+use crate::memory;
+use crate::memory::manager::attr::MemoryAttr;
+use crate::memory::manager::handler::ByFrameLazy;
 use crate::process;
+use crate::sync::futex;
+use crate::timer;
+use crate::trap::frame::TrapFrame;
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::{
+    mem::{size_of, zeroed},
+    slice,
+};
+use rcore_fs::vfs::{FileType, FsError, INode};
+use riscv::addr::Frame;
 
+pub const SYS_DUP3: usize = 24;
+pub const SYS_MKDIRAT: usize = 34;
+pub const SYS_UNLINKAT: usize = 35;
+pub const SYS_OPENAT: usize = 56;
+pub const SYS_CLOSE: usize = 57;
+pub const SYS_PIPE2: usize = 59;
+pub const SYS_GETDENTS64: usize = 61;
+pub const SYS_LSEEK: usize = 62;
 pub const SYS_READ: usize = 63;
 pub const SYS_WRITE: usize = 64;
+pub const SYS_FSTAT: usize = 80;
+pub const SYS_SYNC: usize = 81;
 pub const SYS_EXIT: usize = 93;
-pub const SYS_EXEC: usize = 221;
+pub const SYS_FUTEX: usize = 98;
+pub const SYS_NANOSLEEP: usize = 101;
+pub const SYS_CLOCK_GETTIME: usize = 113;
+pub const SYS_SET_PRIORITY: usize = 140;
+pub const SYS_GETTIMEOFDAY: usize = 169;
+pub const SYS_BRK: usize = 214;
+pub const SYS_MUNMAP: usize = 215;
+pub const SYS_CLONE: usize = 220;
+pub const SYS_EXECVE: usize = 221;
+pub const SYS_MMAP: usize = 222;
+pub const SYS_MPROTECT: usize = 226;
+pub const SYS_WAIT4: usize = 260;
 
-pub fn syscall(id: usize, args: [usize; 3]) -> isize {
-    match id {
-        SYS_WRITE => {
-            print!("{}", args[0] as u8 as char);
-            0
-        }
+// Flags of openat
+const O_ACCMODE: usize = 3;
+const O_RDONLY: usize = 0;
+const O_WRONLY: usize = 1;
+const O_CREAT: usize = 0x40;
+const O_EXCL: usize = 0x80;
+const O_TRUNC: usize = 0x200;
+const O_DIRECTORY: usize = 0x10000;
+// The descriptor is closed by execve
+const O_CLOEXEC: usize = 0x80000;
+
+// Paths are relative to the working directory instead of a directory fd
+const AT_FDCWD: isize = -100;
+// Flag of unlinkat, remove a directory instead of a file
+const AT_REMOVEDIR: usize = 0x200;
+
+// Types of a file in the mode of stat
+const S_IFIFO: u32 = 0o010000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFSOCK: u32 = 0o140000;
+
+// Types of a file in the entries of getdents64
+const DT_FIFO: u8 = 1;
+const DT_CHR: u8 = 2;
+const DT_DIR: u8 = 4;
+const DT_BLK: u8 = 6;
+const DT_REG: u8 = 8;
+const DT_LNK: u8 = 10;
+const DT_SOCK: u8 = 12;
+
+// Operations of futex, private or not makes no difference here
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
+const FUTEX_PRIVATE_FLAG: usize = 128;
+
+// Clocks of clock_gettime
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+
+#[repr(C)]
+struct TimeSpec {
+    sec: usize,
+    nsec: usize,
+}
+
+#[repr(C)]
+struct TimeVal {
+    sec: usize,
+    usec: usize,
+}
+
+// The stat of riscv64 Linux
+#[repr(C)]
+struct Stat {
+    dev: u64,
+    ino: u64,
+    mode: u32,
+    nlink: u32,
+    uid: u32,
+    gid: u32,
+    rdev: u64,
+    __pad1: u64,
+    size: i64,
+    blksize: i32,
+    __pad2: i32,
+    blocks: i64,
+    atime: i64,
+    atime_nsec: u64,
+    mtime: i64,
+    mtime_nsec: u64,
+    ctime: i64,
+    ctime_nsec: u64,
+    __unused: [u32; 2],
+}
+
+// Followed by the name ending with '\0', then padded to 8 bytes
+#[repr(C, packed)]
+struct DirentHeader {
+    ino: u64,
+    off: i64,
+    reclen: u16,
+    type_: u8,
+}
+
+// Most bytes read or written by the kernel at once
+const IO_CHUNK: usize = 4 * PAGE_SIZE;
+
+// Protection and flags of mmap, only private mappings are supported
+const PROT_WRITE: usize = 2;
+const PROT_EXEC: usize = 4;
+const PROT_MASK: usize = 7;
+const MAP_SHARED: usize = 0x01;
+const MAP_FIXED: usize = 0x10;
+const MAP_ANONYMOUS: usize = 0x20;
+
+// Flags of clone, share the address space (create a thread)
+const CLONE_VM: usize = 0x100;
+
+// The value on success, or the error that becomes -errno in a0
+type SyscallResult = Result<usize, Errno>;
+
+pub fn syscall(id: usize, args: [usize; 6], frame: &mut TrapFrame) -> isize {
+    let ret = match id {
+        SYS_DUP3 => sys_dup3(args[0], args[1], args[2]),
+        SYS_MKDIRAT => sys_mkdirat(args[0], args[1] as *const u8, args[2]),
+        SYS_UNLINKAT => sys_unlinkat(args[0], args[1] as *const u8, args[2]),
+        SYS_OPENAT => sys_openat(args[0], args[1] as *const u8, args[2], args[3]),
+        SYS_CLOSE => sys_close(args[0]),
+        SYS_PIPE2 => sys_pipe2(args[0] as *mut i32, args[1]),
+        SYS_GETDENTS64 => sys_getdents64(args[0], args[1] as *mut u8, args[2]),
+        SYS_FSTAT => sys_fstat(args[0], args[1] as *mut Stat),
+        SYS_SYNC => sys_sync(),
+        SYS_LSEEK => sys_lseek(args[0], args[1] as isize, args[2]),
+        SYS_READ => sys_read(args[0], args[1] as *mut u8, args[2]),
+        SYS_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
         SYS_EXIT => {
             process::exit(args[0]);
         }
-        SYS_READ => sys_read(args[0], args[1] as *mut u8, args[2]),
-        SYS_EXEC => sys_exec(args[0] as *const u8),
+        SYS_EXECVE => sys_execve(
+            args[0] as *const u8,
+            args[1] as *const *const u8,
+            args[2] as *const *const u8,
+            frame,
+        ),
+        SYS_FUTEX => sys_futex(args[0], args[1], args[2], args[3]),
+        SYS_NANOSLEEP => sys_nanosleep(args[0] as *const TimeSpec, args[1] as *mut TimeSpec),
+        SYS_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as *mut TimeSpec),
+        SYS_SET_PRIORITY => sys_set_priority(args[0]),
+        SYS_GETTIMEOFDAY => sys_gettimeofday(args[0] as *mut TimeVal),
+        SYS_BRK => sys_brk(args[0]),
+        SYS_MUNMAP => sys_munmap(args[0], args[1]),
+        SYS_CLONE => sys_clone(args[0], args[1], frame),
+        SYS_MMAP => sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
+        SYS_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
+        SYS_WAIT4 => sys_wait4(args[0] as isize, args[1] as *mut i32),
         _ => {
-            panic!("Unknown syscall id {}", id);
+            println!("[kernel] Unknown syscall id {}", id);
+            Err(Errno::ENOSYS)
         }
+    };
+    match ret {
+        Ok(value) => value as isize,
+        Err(errno) => -(errno as isize),
     }
 }
 
-fn sys_read(_fd: usize, base: *mut u8, _len: usize) -> isize {
-    unsafe {
-        *base = crate::fs::stdio::STDIN.pop() as u8;
+// Fail early if [base, base + len) is not in the user part of the current address space,
+// before the syscall does something it can't undo (it may be unmapped by another thread later)
+fn check_user(base: usize, len: usize, write: bool) -> Result<(), Errno> {
+    let valid = process::current_process()
+        .lock()
+        .vm
+        .check_user(base, len, write);
+    if valid {
+        Ok(())
+    } else {
+        Err(Errno::EFAULT)
     }
-    1
 }
 
-pub unsafe fn cstr_to_str(s: *const u8) -> &'static str {
-    use core::{slice, str};
-    let len = (0usize..).find(|&i| *s.add(i) == 0).unwrap();
-    str::from_utf8(slice::from_raw_parts(s, len)).unwrap()
+// User memory is only touched through the frames with the process locked,
+// so another thread can't unmap it meanwhile
+fn copy_from_user(addr: usize, buf: &mut [u8]) -> Result<(), Errno> {
+    let copied = process::current_process().lock().vm.read_user(addr, buf);
+    if copied {
+        Ok(())
+    } else {
+        Err(Errno::EFAULT)
+    }
 }
 
-fn sys_exec(path: *const u8) -> isize {
-    let valid = process::execute(unsafe { cstr_to_str(path) }, Some(process::current_tid()));
-    if valid {
-        process::sleep();
+fn copy_to_user(addr: usize, data: &[u8]) -> Result<(), Errno> {
+    let copied = process::current_process().lock().vm.write_user(addr, data);
+    if copied {
+        Ok(())
+    } else {
+        Err(Errno::EFAULT)
+    }
+}
+
+// Only for plain structures of integers
+fn read_user<T: Copy>(ptr: *const T) -> Result<T, Errno> {
+    let mut value: T = unsafe { zeroed() };
+    let buf = unsafe { slice::from_raw_parts_mut(&mut value as *mut T as *mut u8, size_of::<T>()) };
+    copy_from_user(ptr as usize, buf)?;
+    Ok(value)
+}
+
+fn write_user<T: Copy>(ptr: *mut T, value: T) -> Result<(), Errno> {
+    let data = unsafe { slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>()) };
+    copy_to_user(ptr as usize, data)
+}
+
+fn get_file(fd: usize) -> Result<Arc<File>, Errno> {
+    let file = process::current_process().lock().files.get(fd);
+    file.ok_or(Errno::EBADF)
+}
+
+// At most IO_CHUNK bytes are read at once into a kernel buffer, no lock is held while
+// the file sleeps (stdin and pipes)
+fn sys_read(fd: usize, base: *mut u8, len: usize) -> SyscallResult {
+    let file = get_file(fd)?;
+    check_user(base as usize, len, true)?;
+    let mut buf = vec![0u8; len.min(IO_CHUNK)];
+    let read = file.read(&mut buf)?;
+    copy_to_user(base as usize, &buf[..read])?;
+    Ok(read)
+}
+
+// Copied in IO_CHUNK bytes at a time, a failure after some are written ends it early
+fn sys_write(fd: usize, base: *const u8, len: usize) -> SyscallResult {
+    let file = get_file(fd)?;
+    check_user(base as usize, len, false)?;
+    let mut buf = vec![0u8; len.min(IO_CHUNK)];
+    let mut written = 0;
+    while written < len {
+        let chunk = (len - written).min(IO_CHUNK);
+        let result = copy_from_user(base as usize + written, &mut buf[..chunk])
+            .and_then(|_| file.write(&buf[..chunk]).map_err(Errno::from));
+        match result {
+            Ok(count) => {
+                written += count;
+                if count < chunk {
+                    break;
+                }
+            }
+            Err(errno) if written == 0 => return Err(errno),
+            Err(_) => break,
+        }
+    }
+    Ok(written)
+}
+
+// Relative paths start at dirfd, or at the working directory if it is AT_FDCWD
+fn sys_openat(dirfd: usize, path: *const u8, flags: usize, mode: usize) -> SyscallResult {
+    let path = read_path(path)?;
+    let base = lookup_base(dirfd)?;
+    let inode = if flags & O_CREAT != 0 {
+        let (dir, name) = split_path(&path)?;
+        let dir = base.lookup(dir)?;
+        match dir.find(name) {
+            Ok(_) if flags & O_EXCL != 0 => return Err(Errno::EEXIST),
+            Ok(inode) => inode,
+            Err(FsError::EntryNotFound) => dir.create(name, FileType::File, mode as u32)?,
+            Err(err) => return Err(err.into()),
+        }
+    } else {
+        base.lookup(&path)?
+    };
+
+    let mode = flags & O_ACCMODE;
+    let is_dir = inode.metadata()?.type_ == FileType::Dir;
+    if flags & O_DIRECTORY != 0 && !is_dir {
+        return Err(Errno::ENOTDIR);
+    }
+    if is_dir && mode != O_RDONLY {
+        return Err(Errno::EISDIR);
+    }
+    if flags & O_TRUNC != 0 && mode != O_RDONLY {
+        inode.resize(0)?;
+    }
+    let file = File::new(inode, mode != O_WRONLY, mode != O_RDONLY);
+    let fd = process::current_process()
+        .lock()
+        .files
+        .add(Arc::new(file), flags & O_CLOEXEC != 0);
+    fd.ok_or(Errno::EMFILE)
+}
+
+fn sys_mkdirat(dirfd: usize, path: *const u8, mode: usize) -> SyscallResult {
+    let path = read_path(path)?;
+    let (dir, name) = split_path(&path)?;
+    let dir = lookup_base(dirfd)?.lookup(dir)?;
+    dir.create(name, FileType::Dir, mode as u32)?;
+    Ok(0)
+}
+
+// A directory is removed only with AT_REMOVEDIR (and only if it's empty)
+fn sys_unlinkat(dirfd: usize, path: *const u8, flags: usize) -> SyscallResult {
+    let path = read_path(path)?;
+    let (dir, name) = split_path(&path)?;
+    if name == "." || name == ".." {
+        return Err(Errno::EINVAL);
+    }
+    let dir = lookup_base(dirfd)?.lookup(dir)?;
+    let is_dir = dir.find(name)?.metadata()?.type_ == FileType::Dir;
+    if flags & AT_REMOVEDIR != 0 && !is_dir {
+        return Err(Errno::ENOTDIR);
+    }
+    if flags & AT_REMOVEDIR == 0 && is_dir {
+        return Err(Errno::EISDIR);
+    }
+    dir.unlink(name)?;
+    Ok(0)
+}
+
+// The offset of a directory is the index of the next entry, return the bytes filled
+fn sys_getdents64(fd: usize, base: *mut u8, len: usize) -> SyscallResult {
+    let file = get_file(fd)?;
+    check_user(base as usize, len, true)?;
+    let inode = file.inode();
+    if inode.metadata()?.type_ != FileType::Dir {
+        return Err(Errno::ENOTDIR);
+    }
+
+    // Filled in the kernel, then copied out at once
+    let mut entries: Vec<u8> = Vec::new();
+    let mut index = file.seek(0, SEEK_CUR)?;
+    loop {
+        let name = match inode.get_entry(index) {
+            Ok(name) => name,
+            Err(FsError::EntryNotFound) => break,
+            Err(err) => return Err(err.into()),
+        };
+        let metadata = inode.find(&name)?.metadata()?;
+        let reclen = (size_of::<DirentHeader>() + name.len() + 1 + 7) & !7;
+        if entries.len() + reclen > len {
+            // Not even one entry fits
+            if entries.is_empty() {
+                return Err(Errno::EINVAL);
+            }
+            break;
+        }
+        let header = DirentHeader {
+            ino: metadata.inode as u64,
+            off: (index + 1) as i64,
+            reclen: reclen as u16,
+            type_: dirent_type(metadata.type_),
+        };
+        let start = entries.len();
+        entries.resize(start + reclen, 0);
+        unsafe {
+            (entries.as_mut_ptr().add(start) as *mut DirentHeader).write_unaligned(header);
+        }
+        let name_start = start + size_of::<DirentHeader>();
+        entries[name_start..name_start + name.len()].copy_from_slice(name.as_bytes());
+        index += 1;
+    }
+    copy_to_user(base as usize, &entries)?;
+    file.seek(index as isize, SEEK_SET)?;
+    Ok(entries.len())
+}
+
+fn sys_fstat(fd: usize, stat: *mut Stat) -> SyscallResult {
+    let file = get_file(fd)?;
+    check_user(stat as usize, size_of::<Stat>(), true)?;
+    let metadata = file.inode().metadata()?;
+    let stat_ = Stat {
+        dev: metadata.dev as u64,
+        ino: metadata.inode as u64,
+        mode: mode_type(metadata.type_) | metadata.mode as u32,
+        nlink: metadata.nlinks as u32,
+        uid: metadata.uid as u32,
+        gid: metadata.gid as u32,
+        rdev: metadata.rdev as u64,
+        __pad1: 0,
+        size: metadata.size as i64,
+        blksize: metadata.blk_size as i32,
+        __pad2: 0,
+        blocks: metadata.blocks as i64,
+        atime: metadata.atime.sec,
+        atime_nsec: metadata.atime.nsec as u64,
+        mtime: metadata.mtime.sec,
+        mtime_nsec: metadata.mtime.nsec as u64,
+        ctime: metadata.ctime.sec,
+        ctime_nsec: metadata.ctime.nsec as u64,
+        __unused: [0; 2],
+    };
+    write_user(stat, stat_)?;
+    Ok(0)
+}
+
+// Write the changes back to the device (the in-memory image is dumped over the console)
+fn sys_sync() -> SyscallResult {
+    ROOT_INODE.fs().sync()?;
+    Ok(0)
+}
+
+fn sys_close(fd: usize) -> SyscallResult {
+    let file = process::current_process().lock().files.remove(fd);
+    match file {
+        Some(_) => Ok(0),
+        None => Err(Errno::EBADF),
+    }
+}
+
+// fds[0] is the read end, fds[1] is the write end
+fn sys_pipe2(fds: *mut i32, flags: usize) -> SyscallResult {
+    if flags & !O_CLOEXEC != 0 {
+        return Err(Errno::EINVAL);
+    }
+    check_user(fds as usize, 2 * size_of::<i32>(), true)?;
+    let (reader, writer) = pipe::new_pipe();
+    let cloexec = flags & O_CLOEXEC != 0;
+    let (read_fd, write_fd) = {
+        let current = process::current_process();
+        let mut current = current.lock();
+        let read_fd = current
+            .files
+            .add(Arc::new(File::new(reader, true, false)), cloexec)
+            .ok_or(Errno::EMFILE)?;
+        match current
+            .files
+            .add(Arc::new(File::new(writer, false, true)), cloexec)
+        {
+            Some(write_fd) => (read_fd, write_fd),
+            None => {
+                current.files.remove(read_fd);
+                return Err(Errno::EMFILE);
+            }
+        }
+    };
+    // Unmapped meanwhile by another thread
+    if let Err(errno) = write_user(fds as *mut [i32; 2], [read_fd as i32, write_fd as i32]) {
+        let current = process::current_process();
+        let mut current = current.lock();
+        current.files.remove(read_fd);
+        current.files.remove(write_fd);
+        return Err(errno);
+    }
+    Ok(0)
+}
+
+// The file at newfd is closed first, both descriptors share the offset then
+fn sys_dup3(oldfd: usize, newfd: usize, flags: usize) -> SyscallResult {
+    if oldfd == newfd || flags & !O_CLOEXEC != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let file = get_file(oldfd)?;
+    if newfd >= MAX_FDS {
+        return Err(Errno::EBADF);
+    }
+    // Dropped out of the lock, closing a pipe end wakes up others
+    let closed = process::current_process()
+        .lock()
+        .files
+        .set(newfd, file, flags & O_CLOEXEC != 0);
+    drop(closed);
+    Ok(newfd)
+}
+
+fn sys_lseek(fd: usize, offset: isize, whence: usize) -> SyscallResult {
+    let file = get_file(fd)?;
+    Ok(file.seek(offset, whence)?)
+}
+
+// Where a relative path starts, absolute paths ignore it
+fn lookup_base(dirfd: usize) -> Result<Arc<dyn INode>, Errno> {
+    if dirfd as isize == AT_FDCWD {
+        Ok(process::current_process().lock().cwd_inode()?)
+    } else {
+        Ok(get_file(dirfd)?.inode())
+    }
+}
+
+// Split into the parent directory and the last name, e.g. "a/b/" gives ("a", "b")
+fn split_path(path: &str) -> Result<(&str, &str), Errno> {
+    let path = path.trim_end_matches('/');
+    let (dir, name) = match path.rfind('/') {
+        Some(0) => ("/", &path[1..]),
+        Some(pos) => (&path[..pos], &path[pos + 1..]),
+        None => (".", path),
+    };
+    if name.is_empty() {
+        return Err(Errno::EINVAL);
+    }
+    Ok((dir, name))
+}
+
+fn mode_type(type_: FileType) -> u32 {
+    match type_ {
+        FileType::File => S_IFREG,
+        FileType::Dir => S_IFDIR,
+        FileType::SymLink => S_IFLNK,
+        FileType::CharDevice => S_IFCHR,
+        FileType::BlockDevice => S_IFBLK,
+        FileType::NamedPipe => S_IFIFO,
+        FileType::Socket => S_IFSOCK,
+    }
+}
+
+fn dirent_type(type_: FileType) -> u8 {
+    match type_ {
+        FileType::File => DT_REG,
+        FileType::Dir => DT_DIR,
+        FileType::SymLink => DT_LNK,
+        FileType::CharDevice => DT_CHR,
+        FileType::BlockDevice => DT_BLK,
+        FileType::NamedPipe => DT_FIFO,
+        FileType::Socket => DT_SOCK,
+    }
+}
+
+// Copy a NUL terminated string from user memory, to the end of a page at a time
+// It takes at most max bytes with the NUL, or it's too_long
+fn read_cstr(s: *const u8, max: usize, too_long: Errno) -> Result<String, Errno> {
+    let mut bytes = Vec::new();
+    let mut addr = s as usize;
+    let mut page = [0u8; PAGE_SIZE];
+    loop {
+        let len = PAGE_SIZE - addr % PAGE_SIZE;
+        copy_from_user(addr, &mut page[..len])?;
+        let end = page[..len].iter().position(|&c| c == 0);
+        bytes.extend_from_slice(&page[..end.unwrap_or(len)]);
+        if bytes.len() >= max {
+            return Err(too_long);
+        }
+        if end.is_some() {
+            break;
+        }
+        addr += len;
+    }
+    String::from_utf8(bytes).map_err(|_| Errno::EINVAL)
+}
+
+fn read_path(path: *const u8) -> Result<String, Errno> {
+    read_cstr(path, PATH_MAX, Errno::ENAMETOOLONG)
+}
+
+// Read a NULL terminated array of strings (like argv)
+// The strings with their NULs and pointers take bytes from budget, E2BIG if it runs out
+fn cstr_array(array: *const *const u8, budget: &mut usize) -> Result<Vec<String>, Errno> {
+    let mut strings = Vec::new();
+    if array.is_null() {
+        return Ok(strings);
+    }
+    let mut ptr = array;
+    loop {
+        let s = read_user(ptr)?;
+        if s.is_null() {
+            break;
+        }
+        *budget = budget.checked_sub(size_of::<usize>()).ok_or(Errno::E2BIG)?;
+        let string = read_cstr(s, *budget, Errno::E2BIG)?;
+        *budget -= string.len() + 1;
+        strings.push(string);
+        ptr = unsafe { ptr.add(1) };
+    }
+    Ok(strings)
+}
+
+// Never returns to the caller when succeeded, argc is passed to the new program by a0
+fn sys_execve(
+    path: *const u8,
+    argv: *const *const u8,
+    envp: *const *const u8,
+    frame: &mut TrapFrame,
+) -> SyscallResult {
+    // Other threads would still run on the old address space after it's gone
+    if !process::is_only_thread() {
+        return Err(Errno::EAGAIN);
+    }
+    // Copy everything out before the old address space is gone
+    let path = read_path(path)?;
+    let mut budget = ARG_MAX;
+    let args = cstr_array(argv, &mut budget)?;
+    let envs = cstr_array(envp, &mut budget)?;
+    let inode = process::current_process().lock().lookup(&path)?;
+    let data = inode.read_as_vec()?;
+    // The old program keeps running if the ELF is rejected
+    process::exec(data.as_slice(), args, envs, frame).map_err(|_| Errno::ENOEXEC)
+}
+
+// Fork without CLONE_VM, otherwise a new thread on the given stack
+fn sys_clone(flags: usize, stack: usize, frame: &TrapFrame) -> SyscallResult {
+    if flags & CLONE_VM != 0 {
+        if stack == 0 {
+            return Err(Errno::EINVAL);
+        }
+        process::clone_thread(frame, stack).ok_or(Errno::EAGAIN)
+    } else {
+        process::fork(frame).ok_or(Errno::EAGAIN)
+    }
+}
+
+// Status is encoded as Linux does (WEXITSTATUS = (status >> 8) & 0xff)
+fn sys_wait4(pid: isize, status: *mut i32) -> SyscallResult {
+    if !status.is_null() {
+        check_user(status as usize, size_of::<i32>(), true)?;
+    }
+    let target = if pid == -1 { None } else { Some(pid as usize) };
+    let (tid, code) = process::wait(target).ok_or(Errno::ECHILD)?;
+    if !status.is_null() {
+        write_user(status, ((code & 0xff) << 8) as i32)?;
+    }
+    Ok(tid)
+}
+
+// Like Linux, return the new break, or the current one if it can't be moved (brk(0) asks for it)
+fn sys_brk(brk: usize) -> SyscallResult {
+    let current = process::current_process();
+    let mut current = current.lock();
+    if brk != 0 {
+        current.set_brk(brk);
+    }
+    Ok(current.brk)
+}
+
+const USER_STACK_END: usize = USER_STACK_OFFSET + USER_STACK_SIZE;
+
+// The range of a user mapping, [addr, addr + len) rounded to pages
+// It's in the canonical user half or the stack slot, never in the kernel
+fn user_range(addr: usize, len: usize) -> Result<(usize, usize), Errno> {
+    if addr % PAGE_SIZE != 0 || len == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let end = addr
+        .checked_add(len)
+        .and_then(|end| end.checked_add(PAGE_SIZE - 1))
+        .ok_or(Errno::EINVAL)?
+        / PAGE_SIZE
+        * PAGE_SIZE;
+    if end > USER_SPACE_END && !(addr >= USER_STACK_OFFSET && end <= USER_STACK_END) {
+        return Err(Errno::EINVAL);
+    }
+    Ok((addr, end))
+}
+
+// PROT_NONE pages are mapped without the user bit, so any access from the user faults
+// Pages are always readable unless they are PROT_NONE
+fn prot_to_attr(prot: usize) -> Result<MemoryAttr, Errno> {
+    if prot & !PROT_MASK != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut attr = MemoryAttr::new();
+    if prot != 0 {
+        attr = attr.set_user();
+    }
+    if prot & PROT_WRITE == 0 {
+        attr = attr.set_read_only();
+    }
+    if prot & PROT_EXEC != 0 {
+        attr = attr.set_executable();
+    }
+    Ok(attr)
+}
+
+// Read len bytes (page aligned) of the file into new frames, pages past the end of the file are zeros
+// Frames are filled one by one, so the file is never held in the kernel heap
+fn read_frames(fd: usize, offset: usize, len: usize) -> Result<Vec<Frame>, Errno> {
+    let file = get_file(fd)?;
+    let mut frames = Vec::new();
+    for i in 0..len / PAGE_SIZE {
+        let frame = match memory::frame_alloc() {
+            Some(frame) => frame,
+            None => {
+                free_frames(frames);
+                return Err(Errno::ENOMEM);
+            }
+        };
+        let paddr = frame.start_address().as_usize();
+        frames.push(frame);
+        let page = unsafe {
+            slice::from_raw_parts_mut(memory::paddr_to_vaddr(paddr) as *mut u8, PAGE_SIZE)
+        };
+        for byte in page.iter_mut() {
+            *byte = 0;
+        }
+        if let Err(error) = file.read_at(offset.saturating_add(i * PAGE_SIZE), page) {
+            free_frames(frames);
+            return Err(error.into());
+        }
+    }
+    Ok(frames)
+}
+
+fn free_frames(frames: Vec<Frame>) {
+    for frame in frames {
+        memory::frame_dealloc(frame);
+    }
+}
+
+// Anonymous pages are allocated when touched, a file is copied in at once
+// Without MAP_FIXED, addr is only a hint
+fn sys_mmap(
+    addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> SyscallResult {
+    if flags & MAP_SHARED != 0 || offset % PAGE_SIZE != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let attr = prot_to_attr(prot)?;
+    let hint = if addr == 0 { USER_MMAP_BASE } else { addr };
+    let (hint, end) = user_range(hint / PAGE_SIZE * PAGE_SIZE, len)?;
+    let len = end - hint;
+    if flags & MAP_FIXED != 0 && addr != hint {
+        return Err(Errno::EINVAL);
+    }
+
+    if !memory::enough_frames(len / PAGE_SIZE) {
+        return Err(Errno::ENOMEM);
+    }
+    let limit = if hint >= USER_SPACE_END {
+        USER_STACK_END
+    } else {
+        USER_SPACE_END
+    };
+
+    // Read the file before taking the process
+    let frames = if flags & MAP_ANONYMOUS == 0 {
+        Some(read_frames(fd, offset, len)?)
+    } else {
+        None
+    };
+
+    let current = process::current_process();
+    let mut current = current.lock();
+    let start = if flags & MAP_FIXED != 0 {
+        current.vm.remove_range(hint, end);
+        hint
+    } else {
+        match current.vm.find_free_area(hint, len, limit) {
+            Some(start) => start,
+            None => {
+                if let Some(frames) = frames {
+                    free_frames(frames);
+                }
+                return Err(Errno::ENOMEM);
+            }
+        }
+    };
+    match frames {
+        Some(frames) => current.vm.push_frames(start, attr, frames),
+        None => current
+            .vm
+            .push(start, start + len, attr, ByFrameLazy::new(), None),
+    }
+    Ok(start)
+}
+
+// The frames are freed, unmapping nothing is not an error
+fn sys_munmap(addr: usize, len: usize) -> SyscallResult {
+    let (start, end) = user_range(addr, len)?;
+    process::current_process()
+        .lock()
+        .vm
+        .remove_range(start, end);
+    Ok(0)
+}
+
+fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SyscallResult {
+    let (start, end) = user_range(addr, len)?;
+    let attr = prot_to_attr(prot)?;
+    let covered = process::current_process()
+        .lock()
+        .vm
+        .protect_range(start, end, attr);
+    if covered {
+        Ok(0)
+    } else {
+        Err(Errno::ENOMEM)
+    }
+}
+
+// Only for the current thread, 0 is not a priority
+fn sys_set_priority(priority: usize) -> SyscallResult {
+    if priority == 0 || priority > process::MAX_PRIORITY {
+        return Err(Errno::EINVAL);
+    }
+    process::set_priority(priority);
+    Ok(0)
+}
+
+// Nothing interrupts the sleep, so the remaining time is always 0
+fn sys_nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> SyscallResult {
+    let req = read_user(req)?;
+    if !rem.is_null() {
+        check_user(rem as usize, size_of::<TimeSpec>(), true)?;
+    }
+    if (req.sec as isize) < 0 || req.nsec >= 1_000_000_000 {
+        return Err(Errno::EINVAL);
+    }
+    // A huge one sleeps forever
+    let ns = (req.sec as u64)
+        .saturating_mul(1_000_000_000)
+        .saturating_add(req.nsec as u64);
+    timer::sleep_until(timer::now().saturating_add(timer::ns_to_ticks(ns)));
+    if !rem.is_null() {
+        write_user(rem, TimeSpec { sec: 0, nsec: 0 })?;
+    }
+    Ok(0)
+}
+
+fn sys_clock_gettime(clock: usize, tp: *mut TimeSpec) -> SyscallResult {
+    let ns = match clock {
+        CLOCK_REALTIME => timer::wall_ns(),
+        CLOCK_MONOTONIC => timer::monotonic_ns(),
+        _ => return Err(Errno::EINVAL),
+    };
+    write_user(
+        tp,
+        TimeSpec {
+            sec: (ns / 1_000_000_000) as usize,
+            nsec: (ns % 1_000_000_000) as usize,
+        },
+    )?;
+    Ok(0)
+}
+
+// The timezone argument is obsolete and ignored
+fn sys_gettimeofday(tv: *mut TimeVal) -> SyscallResult {
+    let us = timer::wall_ns() / 1000;
+    write_user(
+        tv,
+        TimeVal {
+            sec: (us / 1_000_000) as usize,
+            usec: (us % 1_000_000) as usize,
+        },
+    )?;
+    Ok(0)
+}
+
+// Timeouts are not supported
+fn sys_futex(uaddr: usize, op: usize, val: usize, timeout: usize) -> SyscallResult {
+    if uaddr % size_of::<u32>() != 0 {
+        return Err(Errno::EINVAL);
+    }
+    check_user(uaddr, size_of::<u32>(), false)?;
+    let key = (process::current_process().lock().vm.id(), uaddr);
+    match op & !FUTEX_PRIVATE_FLAG {
+        FUTEX_WAIT => {
+            if timeout != 0 {
+                return Err(Errno::EINVAL);
+            }
+            futex::wait(key, || read_user(uaddr as *const u32), val as u32)?;
+            Ok(0)
+        }
+        FUTEX_WAKE => Ok(futex::wake(key, val)),
+        _ => Err(Errno::ENOSYS),
     }
-    0
 }
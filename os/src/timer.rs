@@ -1,20 +1,96 @@
+use crate::drivers::devices;
+use crate::memory::paddr_to_vaddr;
+use crate::process::{sleep_with, wake_up, ThreadID};
 use crate::sbi::set_timer;
+use alloc::collections::BinaryHeap;
+use core::cmp::Reverse;
+use lazy_static::*;
 use riscv::register::{sie, time};
+use spin::Mutex;
 
 pub static mut TICKS: usize = 0;
 
 static TIME_BASE: u64 = 100000;
 
+// Frequency of time::read() on QEMU virt
+pub const CLOCK_FREQ: u64 = 10_000_000;
+
+// Wall time in nanoseconds when time::read() was 0
+static mut BOOT_TIME_NS: u64 = 0;
+
+// Sleeping threads ordered by their deadlines (in ticks of time::read())
+lazy_static! {
+    static ref TIMERS: Mutex<BinaryHeap<Reverse<(u64, ThreadID)>>> = Mutex::new(BinaryHeap::new());
+}
+
 pub fn initialize() {
+    unsafe {
+        // Goldfish RTC of QEMU virt, nanoseconds since the epoch
+        let rtc = paddr_to_vaddr(devices().rtc.base) as *const u32;
+        // The high half is latched when the low half is read
+        let low = rtc.read_volatile() as u64;
+        let high = rtc.add(1).read_volatile() as u64;
+        BOOT_TIME_NS = ((high << 32) | low).saturating_sub(ticks_to_ns(now()));
+    }
+    initialize_other();
+    println!("[kernel] Timer initialized.");
+}
+
+// Every hart programs its own timer
+pub fn initialize_other() {
     unsafe {
         TICKS = 0;
         sie::set_stimer();
     }
 
     set_next_event();
-    println!("[kernel] Timer initialized.");
 }
 
 pub fn set_next_event() {
-    set_timer(time::read() as u64 + TIME_BASE);
+    set_timer(now() + TIME_BASE);
+}
+
+pub fn now() -> u64 {
+    time::read() as u64
+}
+
+pub fn ticks_to_ns(ticks: u64) -> u64 {
+    ticks * (1_000_000_000 / CLOCK_FREQ)
+}
+
+pub fn ns_to_ticks(ns: u64) -> u64 {
+    ns / (1_000_000_000 / CLOCK_FREQ)
+}
+
+// Nanoseconds since boot
+pub fn monotonic_ns() -> u64 {
+    ticks_to_ns(now())
+}
+
+// Nanoseconds since the epoch
+pub fn wall_ns() -> u64 {
+    unsafe { BOOT_TIME_NS + monotonic_ns() }
+}
+
+// Sleep till now() reaches the deadline (woken up by the timer interrupt after that)
+pub fn sleep_until(deadline: u64) {
+    sleep_with(|id| TIMERS.lock().push(Reverse((deadline, id))));
+}
+
+// Wake up the threads whose deadlines have passed
+pub fn wake_expired() {
+    let now = now();
+    loop {
+        let id = {
+            let mut timers = TIMERS.lock();
+            match timers.peek() {
+                Some(&Reverse((deadline, id))) if deadline <= now => {
+                    timers.pop();
+                    id
+                }
+                _ => break,
+            }
+        };
+        wake_up(id);
+    }
 }
@@ -2,6 +2,7 @@ use riscv::register::{scause::Scause, sstatus::Sstatus};
 
 // C-like Memory layout (in order)
 #[repr(C)]
+#[derive(Clone)]
 pub struct TrapFrame {
     pub x: [usize; 32],   // General purpose registers
     pub sstatus: Sstatus, // Supervisor status register
@@ -31,6 +31,35 @@ trap_from_kernel:
     // csrr rd, csr (read csr into rd)
     csrr sp, sscratch
 
+    // The kernel stacks are in 2 MB slots of [0xffffffff80000000, 0xffffffffc0000000) (see consts.rs)
+    // Each is [guard page, stack, unmapped upper half], sp near the guard page or in an unmapped part
+    // means the stack overflowed: the trap frame can't be saved there, use the overflow stack
+    // (sscratch keeps the old sp for the trap frame)
+    srai sp, sp, 30
+    addi sp, sp, 2
+    bnez sp, 1f
+
+    // sp = (sp % 2 MB) / 32, in the guard page or without room for a trap frame above it
+    csrr sp, sscratch
+    slli sp, sp, 43
+    srli sp, sp, 48
+    addi sp, sp, -(4096 + 36 * WSIZE) / 32
+    bltz sp, 2f
+
+    // sp = bit 20, in the upper half
+    csrr sp, sscratch
+    slli sp, sp, 43
+    srli sp, sp, 63
+    beqz sp, 1f
+
+2:
+    lui sp, %hi(overflow_stack_top)
+    addi sp, sp, %lo(overflow_stack_top)
+    j trap_from_user
+
+1:
+    csrr sp, sscratch
+
 trap_from_user:
     // Allocate frame stack
     addi sp, sp, -36 * WSIZE
@@ -83,6 +112,12 @@ trap_from_user:
     STORE s2, 33
     STORE s3, 34
     STORE s4, 35
+
+    // From U mode tp belongs to the user, get the hart id back from above the trap frame
+    andi s0, s1, 1 << 8
+    bnez s0, 1f
+    ld tp, 36 * WSIZE(sp)
+1:
 .endm
 
 // Restore the status
@@ -107,6 +142,11 @@ to_user:
     // let sscratch = s0 (kernel stack)
     csrw sscratch, s0
 
+    // Keep the hart id above the trap frame and restore the user tp
+    // Returning to S mode keeps tp, the thread may have moved to another hart
+    sd tp, 0(s0)
+    LOAD x4, 4
+
 to_kernel:
     // Restore sstatus, sepc
     csrw sstatus, s1
@@ -115,7 +155,6 @@ to_kernel:
     // Restore registers except x0 and x2
     LOAD x1, 1
     LOAD x3, 3
-    LOAD x4, 4
     LOAD x5, 5
     LOAD x6, 6
     LOAD x7, 7
@@ -160,4 +199,13 @@ __trap_entry:
 
 __trap_ret:
     RESTORE_ALL
-    sret
\ No newline at end of file
+    sret
+
+    // The trap handler reports the overflow of a kernel stack here, shared by all the harts
+    .section .bss
+    .align 12
+    .global overflow_stack
+overflow_stack:
+    .space 4096 * 4
+    .global overflow_stack_top
+overflow_stack_top:
\ No newline at end of file
@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate user;
+
+use alloc::string::String;
+use user::env::args;
+use user::errno::Errno;
+use user::io::{STDIN, STDOUT};
+use user::syscall::{sys_close, sys_open, sys_read, sys_write, O_RDONLY};
+
+#[no_mangle]
+pub fn main() -> usize {
+    let args = args();
+    // Read stdin without a path, e.g. "rust/hello | rust/cat"
+    let fd = if args.len() < 2 {
+        STDIN
+    } else {
+        let mut path = String::from(args[1]);
+        path.push('\0');
+        let fd = sys_open(path.as_ptr(), O_RDONLY);
+        if let Some(errno) = Errno::from_ret(fd) {
+            println!("cat: cannot open {}: {}", args[1], errno);
+            return 1;
+        }
+        fd as usize
+    };
+    let mut buf = [0u8; 256];
+    loop {
+        let len = sys_read(fd, buf.as_mut_ptr(), buf.len());
+        if len <= 0 {
+            break;
+        }
+        sys_write(STDOUT, buf.as_ptr(), len as usize);
+    }
+    if fd != STDIN {
+        sys_close(fd);
+    }
+    0
+}
@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use user::errno::Errno;
+use user::syscall::{sys_execve, sys_thread_spawn};
+use user::time::sleep_ms;
+
+const STACK_SIZE: usize = 0x4000;
+static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+static STOP: AtomicBool = AtomicBool::new(false);
+
+fn worker(_arg: usize) -> usize {
+    while !STOP.load(Ordering::SeqCst) {
+        sleep_ms(10);
+    }
+    0
+}
+
+fn exec_hello() -> Option<Errno> {
+    let path = "rust/hello\0";
+    let argv = [path.as_ptr(), core::ptr::null()];
+    Errno::from_ret(sys_execve(path.as_ptr(), argv.as_ptr(), core::ptr::null()))
+}
+
+// execve fails while another thread is running, and works once it has exited
+#[no_mangle]
+pub fn main() -> usize {
+    let stack_top = unsafe { STACK.as_ptr() as usize + STACK_SIZE };
+    sys_thread_spawn(worker, 0, stack_top);
+    match exec_hello() {
+        Some(Errno::EAGAIN) => println!("exec_thread: execve with a thread running: EAGAIN"),
+        Some(errno) => {
+            println!("exec_thread: execve with a thread running: {}", errno);
+            return 1;
+        }
+        None => unreachable!(),
+    }
+
+    STOP.store(true, Ordering::SeqCst);
+    // The thread is gone a bit after it returns
+    for _ in 0..100 {
+        match exec_hello() {
+            Some(Errno::EAGAIN) => sleep_ms(10),
+            Some(errno) => {
+                println!("exec_thread: execve alone: {}", errno);
+                return 1;
+            }
+            None => unreachable!(),
+        }
+    }
+    println!("exec_thread: the thread never exited");
+    1
+}
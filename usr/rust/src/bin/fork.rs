@@ -0,0 +1,21 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user;
+
+use user::syscall::sys_fork;
+
+#[no_mangle]
+pub fn main() -> usize {
+    let mut value = 1;
+    let tid = sys_fork();
+    if tid == 0 {
+        // The page is copied here, the parent still sees its own value
+        value = 2;
+        println!("I am the child, value = {}", value);
+    } else {
+        println!("I am the parent of {}, value = {}", tid, value);
+    }
+    0
+}
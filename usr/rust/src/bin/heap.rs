@@ -0,0 +1,33 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate user;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+// Allocate a few megabytes, the heap grows by brk
+#[no_mangle]
+pub fn main() -> usize {
+    let mut text = String::new();
+    for i in 0..1000 {
+        text.push_str(&format!("line {}\n", i));
+    }
+    println!("heap: a string of {} bytes", text.len());
+
+    let mut blocks = Vec::new();
+    for i in 0..4 {
+        let block: Vec<u8> = (0..0x100000).map(|j| (i + j) as u8).collect();
+        blocks.push(block);
+    }
+    let sum: usize = blocks
+        .iter()
+        .map(|block| block.iter().map(|&byte| byte as usize).sum::<usize>())
+        .sum();
+    println!("heap: {} blocks of 1 MB, sum {}", blocks.len(), sum);
+    0
+}
@@ -6,8 +6,13 @@ extern crate alloc;
 #[macro_use]
 extern crate user;
 
+use user::env::args;
+
 #[no_mangle]
 pub fn main() -> usize {
     println!("Hello, world! (from user mode binary)");
+    for (i, arg) in args().iter().enumerate().skip(1) {
+        println!("argv[{}] = {}", i, arg);
+    }
     0
 }
@@ -0,0 +1,57 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate user;
+
+use alloc::string::String;
+use user::env::args;
+use user::errno::Errno;
+use user::fs::{read_dir, Stat, DT_DIR};
+use user::syscall::{sys_close, sys_fstat, sys_open, O_RDONLY};
+
+// List a directory (the working directory by default), e.g. "rust/ls rust"
+#[no_mangle]
+pub fn main() -> usize {
+    let args = args();
+    let name = args.get(1).cloned().unwrap_or(".");
+    let mut path = String::from(name);
+    path.push('\0');
+    let fd = sys_open(path.as_ptr(), O_RDONLY);
+    if let Some(errno) = Errno::from_ret(fd) {
+        println!("ls: cannot access {}: {}", name, errno);
+        return 1;
+    }
+    let fd = fd as usize;
+
+    let mut stat = Stat::default();
+    let ret = sys_fstat(fd, &mut stat);
+    let code = if let Some(errno) = Errno::from_ret(ret) {
+        println!("ls: cannot stat {}: {}", name, errno);
+        1
+    } else if !stat.is_dir() {
+        println!("{} {}", name, stat.size);
+        0
+    } else {
+        match read_dir(fd) {
+            Ok(entries) => {
+                for entry in entries {
+                    if entry.type_ == DT_DIR {
+                        println!("{}/", entry.name);
+                    } else {
+                        println!("{}", entry.name);
+                    }
+                }
+                0
+            }
+            Err(errno) => {
+                println!("ls: cannot read {}: {}", name, errno);
+                1
+            }
+        }
+    };
+    sys_close(fd);
+    code
+}
@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate user;
+
+use alloc::string::String;
+use user::env::args;
+use user::errno::Errno;
+use user::syscall::sys_mkdir;
+
+// Create the directories, e.g. "rust/mkdir tmp tmp/a"
+#[no_mangle]
+pub fn main() -> usize {
+    let args = args();
+    if args.len() < 2 {
+        println!("usage: mkdir <path>...");
+        return 1;
+    }
+    let mut code = 0;
+    for name in &args[1..] {
+        let mut path = String::from(*name);
+        path.push('\0');
+        if let Some(errno) = Errno::from_ret(sys_mkdir(path.as_ptr())) {
+            println!("mkdir: cannot create {}: {}", name, errno);
+            code = 1;
+        }
+    }
+    code
+}
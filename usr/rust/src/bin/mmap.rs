@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate user;
+
+use alloc::string::String;
+use core::slice;
+use user::env::args;
+use user::errno::Errno;
+use user::io::STDOUT;
+use user::syscall::{
+    sys_close, sys_mmap, sys_mprotect, sys_munmap, sys_open, sys_write, MAP_ANONYMOUS, MAP_PRIVATE,
+    O_RDONLY, PROT_READ, PROT_WRITE,
+};
+
+const PAGE_SIZE: usize = 4096;
+
+// Map some anonymous pages, then the file given (if any) and print it
+#[no_mangle]
+pub fn main() -> usize {
+    let len = PAGE_SIZE * 4;
+    let ret = sys_mmap(
+        0,
+        len,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+        0,
+        0,
+    );
+    if let Some(errno) = Errno::from_ret(ret) {
+        println!("mmap: anonymous mapping failed: {}", errno);
+        return 1;
+    }
+    // The pages are allocated here, when they are touched
+    let pages = unsafe { slice::from_raw_parts_mut(ret as usize as *mut u8, len) };
+    for (i, byte) in pages.iter_mut().enumerate() {
+        *byte = (i / PAGE_SIZE) as u8;
+    }
+    println!(
+        "mmap: {} bytes at {:#x}, last byte {}",
+        len,
+        ret,
+        pages[len - 1]
+    );
+    // Writing the first page after this would kill us
+    sys_mprotect(ret as usize, PAGE_SIZE, PROT_READ);
+    println!("mmap: first byte {} after mprotect", pages[0]);
+    sys_munmap(ret as usize, len);
+
+    let args = args();
+    if args.len() < 2 {
+        return 0;
+    }
+    let mut path = String::from(args[1]);
+    path.push('\0');
+    let fd = sys_open(path.as_ptr(), O_RDONLY);
+    if let Some(errno) = Errno::from_ret(fd) {
+        println!("mmap: cannot open {}: {}", args[1], errno);
+        return 1;
+    }
+    let ret = sys_mmap(0, PAGE_SIZE, PROT_READ, MAP_PRIVATE, fd as usize, 0);
+    sys_close(fd as usize);
+    if let Some(errno) = Errno::from_ret(ret) {
+        println!("mmap: cannot map {}: {}", args[1], errno);
+        return 1;
+    }
+    // The rest of the page after the file is zeros
+    let page = unsafe { slice::from_raw_parts(ret as usize as *const u8, PAGE_SIZE) };
+    let len = page.iter().position(|&byte| byte == 0).unwrap_or(PAGE_SIZE);
+    sys_write(STDOUT, page.as_ptr(), len);
+    sys_munmap(ret as usize, PAGE_SIZE);
+    0
+}
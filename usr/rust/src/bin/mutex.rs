@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user;
+
+use user::sync::{Condvar, Mutex};
+use user::syscall::sys_thread_spawn;
+
+const THREADS: usize = 4;
+const ROUNDS: usize = 1000;
+const STACK_SIZE: usize = 0x4000;
+static mut STACKS: [[u8; STACK_SIZE]; THREADS] = [[0; STACK_SIZE]; THREADS];
+
+// (counter, finished threads)
+static COUNTER: Mutex<(usize, usize)> = Mutex::new((0, 0));
+static FINISHED: Condvar = Condvar::new();
+
+fn worker(_arg: usize) -> usize {
+    for _ in 0..ROUNDS {
+        COUNTER.lock().0 += 1;
+    }
+    COUNTER.lock().1 += 1;
+    FINISHED.notify_one();
+    0
+}
+
+// Threads add to a counter under a futex mutex, the main thread waits on a condvar
+#[no_mangle]
+pub fn main() -> usize {
+    for i in 0..THREADS {
+        let stack_top = unsafe { STACKS[i].as_ptr() as usize + STACK_SIZE };
+        sys_thread_spawn(worker, i, stack_top);
+    }
+    let mut counter = COUNTER.lock();
+    while counter.1 < THREADS {
+        counter = FINISHED.wait(counter);
+    }
+    println!("counter = {} (expected {})", counter.0, THREADS * ROUNDS);
+    0
+}
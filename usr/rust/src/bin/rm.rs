@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate user;
+
+use alloc::string::String;
+use user::env::args;
+use user::errno::Errno;
+use user::syscall::{sys_unlink, AT_REMOVEDIR};
+
+// Remove files, or empty directories with "-d", e.g. "rust/rm -d tmp"
+#[no_mangle]
+pub fn main() -> usize {
+    let args = args();
+    let (flags, names) = match args.get(1) {
+        Some(&"-d") => (AT_REMOVEDIR, &args[2..]),
+        _ => (0, &args[1..]),
+    };
+    if names.is_empty() {
+        println!("usage: rm [-d] <path>...");
+        return 1;
+    }
+    let mut code = 0;
+    for name in names {
+        let mut path = String::from(*name);
+        path.push('\0');
+        if let Some(errno) = Errno::from_ret(sys_unlink(path.as_ptr(), flags)) {
+            println!("rm: cannot remove {}: {}", name, errno);
+            code = 1;
+        }
+    }
+    code
+}
@@ -10,11 +10,103 @@ const LF: u8 = 0x0au8;
 const CR: u8 = 0x0du8;
 
 use alloc::string::String;
-use user::io::getchar;
-use user::syscall::sys_exec;
+use alloc::vec::Vec;
+use user::errno::Errno;
+use user::io::{getchar, STDIN, STDOUT};
+use user::syscall::{
+    exit_status, sys_close, sys_dup3, sys_execve, sys_exit, sys_fork, sys_pipe, sys_set_priority,
+    sys_wait4,
+};
+
+// Replace the child with the command, e.g. "rust/hello foo bar"
+fn run(command: &str) -> ! {
+    let args: Vec<String> = command
+        .split_whitespace()
+        .map(|arg| {
+            let mut arg = String::from(arg);
+            arg.push('\0');
+            arg
+        })
+        .collect();
+    let mut argv: Vec<*const u8> = args.iter().map(|arg| arg.as_ptr()).collect();
+    argv.push(core::ptr::null());
+
+    let ret = sys_execve(args[0].as_ptr(), argv.as_ptr(), core::ptr::null());
+    match Errno::from_ret(ret) {
+        Some(errno) => println!("{}: {}", args[0].trim_end_matches('\0'), errno),
+        None => println!("Program not found."),
+    }
+    sys_exit(1);
+}
+
+// Fork a child for each command of the pipeline, e.g. "rust/hello | rust/cat"
+// The stdout of a command is connected to the stdin of the next one
+fn execute(line: &str) {
+    let commands: Vec<&str> = line.split('|').map(|command| command.trim()).collect();
+    if commands.iter().any(|command| command.is_empty()) {
+        if commands.len() > 1 {
+            println!("Invalid pipeline.");
+        }
+        return;
+    }
+
+    let mut children = Vec::new();
+    // Read end of the pipe from the previous command
+    let mut input = None;
+    for (i, command) in commands.iter().enumerate() {
+        let output = if i + 1 < commands.len() {
+            let mut fds = [0i32; 2];
+            if let Some(errno) = Errno::from_ret(sys_pipe(&mut fds)) {
+                println!("pipe: {}", errno);
+                break;
+            }
+            Some((fds[0] as usize, fds[1] as usize))
+        } else {
+            None
+        };
+
+        let tid = sys_fork();
+        if tid == 0 {
+            if let Some(read_fd) = input {
+                sys_dup3(read_fd, STDIN);
+                sys_close(read_fd);
+            }
+            if let Some((read_fd, write_fd)) = output {
+                sys_dup3(write_fd, STDOUT);
+                sys_close(read_fd);
+                sys_close(write_fd);
+            }
+            run(command);
+        }
+        children.push(tid);
+
+        // Only the children keep the pipes, so readers see EOF when the writers exit
+        if let Some(read_fd) = input {
+            sys_close(read_fd);
+        }
+        input = match output {
+            Some((read_fd, write_fd)) => {
+                sys_close(write_fd);
+                Some(read_fd)
+            }
+            None => None,
+        };
+    }
+    if let Some(read_fd) = input {
+        sys_close(read_fd);
+    }
+
+    let mut status = 0;
+    for tid in children {
+        sys_wait4(tid as isize, &mut status);
+    }
+    println!("exit status {}", exit_status(status));
+}
 
 #[no_mangle]
 pub fn main() {
+    // Stay responsive when the background jobs are busy
+    sys_set_priority(64);
     println!("[ user ] rCore-OS User shell initialized.");
     let mut line: String = String::new();
     print!(">> ");
@@ -24,7 +116,7 @@ pub fn main() {
             LF | CR => {
                 println!();
                 if !line.is_empty() {
-                    sys_exec(line.as_ptr());
+                    execute(line.as_str());
                     line.clear();
                 }
                 print!(">> ");
@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate user;
+
+use user::env::args;
+use user::time::{sleep_ms, uptime_ms};
+
+// Sleep for the given milliseconds, e.g. "rust/sleep 500"
+#[no_mangle]
+pub fn main() -> usize {
+    let args = args();
+    let ms = match args.get(1).map(|arg| arg.parse::<usize>()) {
+        Some(Ok(ms)) => ms,
+        _ => {
+            println!("usage: sleep <ms>");
+            return 1;
+        }
+    };
+    let start = uptime_ms();
+    sleep_ms(ms);
+    println!("slept for {} ms", uptime_ms() - start);
+    0
+}
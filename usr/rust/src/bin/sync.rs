@@ -0,0 +1,18 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user;
+
+use user::errno::Errno;
+use user::syscall::sys_sync;
+
+// Write the file system back, the image can then be rebuilt on the host by "make undump"
+#[no_mangle]
+pub fn main() -> usize {
+    if let Some(errno) = Errno::from_ret(sys_sync()) {
+        println!("sync: {}", errno);
+        return 1;
+    }
+    0
+}
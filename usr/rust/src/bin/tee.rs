@@ -0,0 +1,47 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate user;
+
+use alloc::string::String;
+use user::env::args;
+use user::errno::Errno;
+use user::io::{STDIN, STDOUT};
+use user::syscall::{sys_close, sys_open, sys_read, sys_write, O_CREAT, O_TRUNC, O_WRONLY};
+
+// Copy stdin to a file and stdout, e.g. "rust/hello | rust/tee hello.txt"
+#[no_mangle]
+pub fn main() -> usize {
+    let args = args();
+    if args.len() < 2 {
+        println!("usage: tee <path>");
+        return 1;
+    }
+    let mut path = String::from(args[1]);
+    path.push('\0');
+    let fd = sys_open(path.as_ptr(), O_WRONLY | O_CREAT | O_TRUNC);
+    if let Some(errno) = Errno::from_ret(fd) {
+        println!("tee: cannot open {}: {}", args[1], errno);
+        return 1;
+    }
+    let fd = fd as usize;
+    let mut buf = [0u8; 256];
+    let mut code = 0;
+    loop {
+        let len = sys_read(STDIN, buf.as_mut_ptr(), buf.len());
+        if len <= 0 {
+            break;
+        }
+        if let Some(errno) = Errno::from_ret(sys_write(fd, buf.as_ptr(), len as usize)) {
+            println!("tee: cannot write {}: {}", args[1], errno);
+            code = 1;
+            break;
+        }
+        sys_write(STDOUT, buf.as_ptr(), len as usize);
+    }
+    sys_close(fd);
+    code
+}
@@ -0,0 +1,23 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user;
+
+use user::syscall::sys_thread_spawn;
+
+const STACK_SIZE: usize = 0x4000;
+static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+fn worker(arg: usize) -> usize {
+    println!("Hello from thread with arg {}", arg);
+    0
+}
+
+#[no_mangle]
+pub fn main() -> usize {
+    let stack_top = unsafe { STACK.as_ptr() as usize + STACK_SIZE };
+    let tid = sys_thread_spawn(worker, 42, stack_top);
+    println!("Spawned thread {}", tid);
+    0
+}
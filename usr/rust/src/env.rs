@@ -0,0 +1,23 @@
+use alloc::vec::Vec;
+use core::{slice, str};
+
+// Set by _start, the strings are on the initial stack
+static mut ARGC: usize = 0;
+static mut ARGV: *const *const u8 = core::ptr::null();
+
+pub fn initialize(argc: usize, argv: *const *const u8) {
+    unsafe {
+        ARGC = argc;
+        ARGV = argv;
+    }
+}
+
+unsafe fn cstr_to_str(s: *const u8) -> &'static str {
+    let len = (0usize..).find(|&i| *s.add(i) == 0).unwrap();
+    str::from_utf8(slice::from_raw_parts(s, len)).unwrap()
+}
+
+// Arguments of the program, the first one is the path
+pub fn args() -> Vec<&'static str> {
+    unsafe { (0..ARGC).map(|i| cstr_to_str(*ARGV.add(i))).collect() }
+}
@@ -0,0 +1,90 @@
+use core::fmt;
+
+// Linux error numbers, a failed syscall returns the negative one
+#[repr(i64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Errno {
+    EPERM = 1,
+    ENOENT = 2,
+    EIO = 5,
+    E2BIG = 7,
+    ENOEXEC = 8,
+    EBADF = 9,
+    ECHILD = 10,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EFAULT = 14,
+    EEXIST = 17,
+    ENOTDIR = 20,
+    EISDIR = 21,
+    EINVAL = 22,
+    EMFILE = 24,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    EPIPE = 32,
+    ENAMETOOLONG = 36,
+    ENOSYS = 38,
+    ENOTEMPTY = 39,
+}
+
+impl Errno {
+    // Get the error from a syscall return value (None if it succeeded)
+    pub fn from_ret(ret: i64) -> Option<Errno> {
+        use Errno::*;
+        let errno = match -ret {
+            1 => EPERM,
+            2 => ENOENT,
+            5 => EIO,
+            7 => E2BIG,
+            8 => ENOEXEC,
+            9 => EBADF,
+            10 => ECHILD,
+            11 => EAGAIN,
+            12 => ENOMEM,
+            14 => EFAULT,
+            17 => EEXIST,
+            20 => ENOTDIR,
+            21 => EISDIR,
+            22 => EINVAL,
+            24 => EMFILE,
+            28 => ENOSPC,
+            29 => ESPIPE,
+            32 => EPIPE,
+            36 => ENAMETOOLONG,
+            38 => ENOSYS,
+            39 => ENOTEMPTY,
+            _ => return None,
+        };
+        Some(errno)
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Errno::*;
+        let message = match self {
+            EPERM => "Operation not permitted",
+            ENOENT => "No such file or directory",
+            EIO => "I/O error",
+            E2BIG => "Argument list too long",
+            ENOEXEC => "Exec format error",
+            EBADF => "Bad file descriptor",
+            ECHILD => "No child processes",
+            EAGAIN => "Try again",
+            ENOMEM => "Out of memory",
+            EFAULT => "Bad address",
+            EEXIST => "File exists",
+            ENOTDIR => "Not a directory",
+            EISDIR => "Is a directory",
+            EINVAL => "Invalid argument",
+            EMFILE => "Too many open files",
+            ENOSPC => "No space left on device",
+            ESPIPE => "Illegal seek",
+            EPIPE => "Broken pipe",
+            ENAMETOOLONG => "File name too long",
+            ENOSYS => "Function not implemented",
+            ENOTEMPTY => "Directory not empty",
+        };
+        f.write_str(message)
+    }
+}
@@ -0,0 +1,84 @@
+use crate::errno::Errno;
+use crate::syscall::sys_getdents64;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::{ptr, str};
+
+// Types of a file in Stat::mode
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+
+// Types of a file in DirEntry::type_
+pub const DT_DIR: u8 = 4;
+pub const DT_REG: u8 = 8;
+
+// Filled by sys_fstat
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u64,
+    __pad1: u64,
+    pub size: i64,
+    pub blksize: i32,
+    __pad2: i32,
+    pub blocks: i64,
+    pub atime: i64,
+    pub atime_nsec: u64,
+    pub mtime: i64,
+    pub mtime_nsec: u64,
+    pub ctime: i64,
+    pub ctime_nsec: u64,
+    __unused: [u32; 2],
+}
+
+impl Stat {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+}
+
+pub struct DirEntry {
+    pub ino: u64,
+    pub type_: u8,
+    pub name: String,
+}
+
+// Read all the entries left in an opened directory
+pub fn read_dir(fd: usize) -> Result<Vec<DirEntry>, Errno> {
+    let mut entries = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let len = sys_getdents64(fd, buf.as_mut_ptr(), buf.len());
+        if let Some(errno) = Errno::from_ret(len) {
+            return Err(errno);
+        }
+        if len == 0 {
+            return Ok(entries);
+        }
+        // ino: u64, off: i64, reclen: u16, type: u8, then the name ending with '\0'
+        let mut pos = 0;
+        while pos < len as usize {
+            let entry = &buf[pos..];
+            let ino = unsafe { ptr::read_unaligned(entry.as_ptr() as *const u64) };
+            let reclen = u16::from_ne_bytes([entry[16], entry[17]]) as usize;
+            let name = &entry[19..reclen];
+            let name_len = name
+                .iter()
+                .position(|&byte| byte == 0)
+                .unwrap_or(name.len());
+            entries.push(DirEntry {
+                ino,
+                type_: entry[18],
+                name: String::from(str::from_utf8(&name[..name_len]).unwrap_or("?")),
+            });
+            pos += reclen;
+        }
+    }
+}
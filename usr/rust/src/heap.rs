@@ -0,0 +1,50 @@
+use crate::syscall::sys_brk;
+use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+
+const PAGE_SIZE: usize = 4096;
+// Grow at least this much at a time, brk is a syscall
+const MIN_GROW: usize = 0x10000;
+
+// The buddy allocator on the heap after the program, it's empty at first
+// and the program break is moved when it runs out of memory
+pub struct BrkHeap {
+    heap: LockedHeap,
+}
+
+impl BrkHeap {
+    pub const fn empty() -> Self {
+        BrkHeap {
+            heap: LockedHeap::empty(),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for BrkHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+        if let Ok(ptr) = heap.alloc(layout) {
+            return ptr.as_ptr();
+        }
+        // Buddy blocks are aligned to their size, twice the block makes sure
+        // that the new range has one whatever the break is
+        let block = layout.size().max(layout.align()).next_power_of_two();
+        let grow = (block * 2).max(MIN_GROW);
+        let grow = (grow + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+        let start = sys_brk(0) as usize;
+        if sys_brk(start + grow) as usize != start + grow {
+            return ptr::null_mut();
+        }
+        heap.add_to_heap(start, start + grow);
+        heap.alloc(layout)
+            .map_or(ptr::null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    // The memory is kept in the heap, the break never goes back
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap
+            .lock()
+            .dealloc(NonNull::new_unchecked(ptr), layout)
+    }
+}
@@ -7,6 +7,7 @@ struct StdOut;
 // stdout (fd = 1)
 // stderr (fd = 2)
 pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
 
 impl fmt::Write for StdOut {
     fn write_str(&mut self, s: &str) -> fmt::Result {
@@ -20,13 +21,12 @@ pub fn _print(args: fmt::Arguments) {
 }
 
 pub fn putchar(ch: char) {
-    sys_write(ch as u8);
+    let ch = ch as u8;
+    sys_write(STDOUT, &ch, 1);
 }
 
 pub fn puts(s: &str) {
-    for ch in s.chars() {
-        putchar(ch);
-    }
+    sys_write(STDOUT, s.as_ptr(), s.len());
 }
 
 pub fn getchar() -> u8 {
@@ -8,19 +8,6 @@ fn main() -> usize {
     panic!("No main() linked");
 }
 
-use crate::DYNAMIC_ALLOCATOR;
-
-// Initialize the dynamic allocator (on heap)
-fn initialize() {
-    const HEAP_SIZE: usize = 0x1000;
-    static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
-    unsafe {
-        DYNAMIC_ALLOCATOR
-            .lock()
-            .init(HEAP.as_ptr() as usize, HEAP_SIZE);
-    }
-}
-
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     let location = info.location().unwrap();
@@ -35,8 +22,8 @@ fn panic(info: &PanicInfo) -> ! {
 }
 
 #[no_mangle]
-pub extern "C" fn _start(_args: isize, _argv: *const u8) -> ! {
-    initialize();
+pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
+    crate::env::initialize(argc, argv);
     sys_exit(main())
 }
 
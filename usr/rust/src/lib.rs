@@ -9,10 +9,16 @@ extern crate alloc;
 #[macro_use]
 pub mod io;
 
+pub mod env;
+pub mod errno;
+pub mod fs;
+pub mod heap;
 pub mod lang;
+pub mod sync;
 pub mod syscall;
+pub mod time;
 
-use buddy_system_allocator::LockedHeap;
+use heap::BrkHeap;
 
 #[global_allocator]
-static DYNAMIC_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static DYNAMIC_ALLOCATOR: BrkHeap = BrkHeap::empty();
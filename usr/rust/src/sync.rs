@@ -0,0 +1,114 @@
+use crate::syscall::{sys_futex, FUTEX_WAIT, FUTEX_WAKE};
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+fn futex_wait(futex: &AtomicU32, val: u32) {
+    sys_futex(
+        futex as *const AtomicU32 as *const u32,
+        FUTEX_WAIT,
+        val as usize,
+    );
+}
+
+fn futex_wake(futex: &AtomicU32, count: usize) {
+    sys_futex(futex as *const AtomicU32 as *const u32, FUTEX_WAKE, count);
+}
+
+// Unlocked
+const UNLOCKED: u32 = 0;
+// Locked and nobody is waiting, so unlocking needs no syscall
+const LOCKED: u32 = 1;
+// Locked and someone may be sleeping in the kernel
+const CONTENDED: u32 = 2;
+
+// Sleeps in the kernel (by futex) when the lock is taken by another thread
+pub struct Mutex<T> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            state: AtomicU32::new(UNLOCKED),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<T> {
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            // Mark it contended, we own it if it was released in the meantime
+            while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+                futex_wait(&self.state, CONTENDED);
+            }
+        }
+        MutexGuard { mutex: self }
+    }
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        if self.mutex.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&self.mutex.state, 1);
+        }
+    }
+}
+
+// Waiters sleep on the sequence number, every notify changes it
+pub struct Condvar {
+    seq: AtomicU32,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            seq: AtomicU32::new(0),
+        }
+    }
+
+    // Release the lock and wait, it's locked again when returning (spurious wake ups may happen)
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let seq = self.seq.load(Ordering::Relaxed);
+        let mutex = guard.mutex;
+        drop(guard);
+        // Returns at once if someone notified after we released the lock
+        futex_wait(&self.seq, seq);
+        mutex.lock()
+    }
+
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        futex_wake(&self.seq, 1);
+    }
+
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        futex_wake(&self.seq, usize::max_value());
+    }
+}
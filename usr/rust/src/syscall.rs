@@ -1,10 +1,70 @@
+use crate::fs::Stat;
+use crate::time::{TimeSpec, TimeVal};
+
 enum Syscall {
+    Dup3 = 24,
+    Mkdirat = 34,
+    Unlinkat = 35,
+    Openat = 56,
+    Close = 57,
+    Pipe2 = 59,
+    Getdents64 = 61,
+    Lseek = 62,
     Write = 64,
+    Fstat = 80,
+    Sync = 81,
     Exit = 93,
+    Futex = 98,
+    Nanosleep = 101,
+    ClockGettime = 113,
+    SetPriority = 140,
+    Gettimeofday = 169,
     Read = 63,
-    Exec = 221,
+    Execve = 221,
+    Clone = 220,
+    Wait4 = 260,
+    Brk = 214,
+    Munmap = 215,
+    Mmap = 222,
+    Mprotect = 226,
 }
 
+// Use the current working directory for openat
+const AT_FDCWD: isize = -100;
+
+// Flags of sys_open
+pub const O_RDONLY: usize = 0;
+pub const O_WRONLY: usize = 1;
+pub const O_RDWR: usize = 2;
+pub const O_CREAT: usize = 0x40;
+pub const O_EXCL: usize = 0x80;
+pub const O_TRUNC: usize = 0x200;
+pub const O_DIRECTORY: usize = 0x10000;
+
+// Flag of sys_unlink, remove an empty directory instead of a file
+pub const AT_REMOVEDIR: usize = 0x200;
+
+// Flags of clone, share the address space (create a thread)
+const CLONE_VM: usize = 0x100;
+
+// Operations of sys_futex
+pub const FUTEX_WAIT: usize = 0;
+pub const FUTEX_WAKE: usize = 1;
+
+// Protection and flags of sys_mmap, only private mappings are supported
+pub const PROT_NONE: usize = 0;
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+pub const PROT_EXEC: usize = 4;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+// Whence of sys_lseek
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
 #[inline(always)]
 fn sys_call(id: Syscall, arg0: usize, arg1: usize, arg2: usize, arg3: usize) -> i64 {
     let id = id as usize;
@@ -22,8 +82,26 @@ fn sys_call(id: Syscall, arg0: usize, arg1: usize, arg2: usize, arg3: usize) ->
     ret
 }
 
-pub fn sys_write(ch: u8) -> i64 {
-    sys_call(Syscall::Write, ch as usize, 0, 0, 0)
+// For the few syscalls with more than 4 arguments
+#[inline(always)]
+fn sys_call6(id: Syscall, args: [usize; 6]) -> i64 {
+    let id = id as usize;
+    let mut ret: i64;
+    unsafe {
+        asm!(
+            "ecall"
+            : "={x10}"(ret)
+            : "{x17}"(id), "{x10}"(args[0]), "{x11}"(args[1]), "{x12}"(args[2]),
+              "{x13}"(args[3]), "{x14}"(args[4]), "{x15}"(args[5])
+            : "memory"
+            : "volatile"
+        );
+    }
+    ret
+}
+
+pub fn sys_write(fd: usize, base: *const u8, len: usize) -> i64 {
+    sys_call(Syscall::Write, fd, base as usize, len, 0)
 }
 
 pub fn sys_exit(code: usize) -> ! {
@@ -31,10 +109,164 @@ pub fn sys_exit(code: usize) -> ! {
     loop {}
 }
 
-pub fn sys_read(fd: usize, base: *const u8, len: usize) -> i64 {
+pub fn sys_read(fd: usize, base: *mut u8, len: usize) -> i64 {
     sys_call(Syscall::Read, fd, base as usize, len, 0)
 }
 
-pub fn sys_exec(path: *const u8) {
-    sys_call(Syscall::Exec, path as usize, 0, 0, 0);
+// Path must end with '\0', return the fd
+// Created files are readable and writable by everyone
+pub fn sys_open(path: *const u8, flags: usize) -> i64 {
+    sys_call(
+        Syscall::Openat,
+        AT_FDCWD as usize,
+        path as usize,
+        flags,
+        0o666,
+    )
+}
+
+pub fn sys_mkdir(path: *const u8) -> i64 {
+    sys_call(Syscall::Mkdirat, AT_FDCWD as usize, path as usize, 0o777, 0)
+}
+
+pub fn sys_unlink(path: *const u8, flags: usize) -> i64 {
+    sys_call(
+        Syscall::Unlinkat,
+        AT_FDCWD as usize,
+        path as usize,
+        flags,
+        0,
+    )
+}
+
+// Fill the buffer with the entries of a directory, return the bytes filled (0 at the end)
+pub fn sys_getdents64(fd: usize, base: *mut u8, len: usize) -> i64 {
+    sys_call(Syscall::Getdents64, fd, base as usize, len, 0)
+}
+
+pub fn sys_fstat(fd: usize, stat: &mut Stat) -> i64 {
+    sys_call(Syscall::Fstat, fd, stat as *mut Stat as usize, 0, 0)
+}
+
+// Write the file system back (the kernel dumps the image over the console)
+pub fn sys_sync() -> i64 {
+    sys_call(Syscall::Sync, 0, 0, 0, 0)
+}
+
+pub fn sys_close(fd: usize) -> i64 {
+    sys_call(Syscall::Close, fd, 0, 0, 0)
+}
+
+// fds[0] is the read end and fds[1] is the write end
+pub fn sys_pipe(fds: &mut [i32; 2]) -> i64 {
+    sys_call(Syscall::Pipe2, fds.as_mut_ptr() as usize, 0, 0, 0)
+}
+
+// Make newfd refer to the file of oldfd (closing the old one at newfd), return newfd
+pub fn sys_dup3(oldfd: usize, newfd: usize) -> i64 {
+    sys_call(Syscall::Dup3, oldfd, newfd, 0, 0)
+}
+
+// Return the new offset
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> i64 {
+    sys_call(Syscall::Lseek, fd, offset as usize, whence, 0)
+}
+
+// Replace the current program, only returns when failed
+// Strings must end with '\0', argv and envp end with a null pointer
+pub fn sys_execve(path: *const u8, argv: *const *const u8, envp: *const *const u8) -> i64 {
+    sys_call(
+        Syscall::Execve,
+        path as usize,
+        argv as usize,
+        envp as usize,
+        0,
+    )
+}
+
+// Return 0 in the child and the tid of the child in the parent
+pub fn sys_fork() -> i64 {
+    sys_call(Syscall::Clone, 0, 0, 0, 0)
+}
+
+// Run entry(arg) in a new thread of this process on the given stack, return its tid
+// The child can not return into our stack frame, so it calls entry and exits in asm
+// Only a0 is changed by the ecall in the parent
+pub fn sys_thread_spawn(entry: fn(usize) -> usize, arg: usize, stack_top: usize) -> i64 {
+    let mut ret: i64;
+    unsafe {
+        asm!(
+            "ecall
+            bnez a0, 1f
+            mv a0, t4
+            jalr t3
+            li a7, 93
+            ecall
+        1:"
+            : "={x10}"(ret)
+            : "{x17}"(Syscall::Clone as usize), "{x10}"(CLONE_VM), "{x11}"(stack_top & !0xf),
+              "{x28}"(entry as usize), "{x29}"(arg)
+            : "memory"
+            : "volatile"
+        );
+    }
+    ret
+}
+
+// FUTEX_WAIT sleeps if *uaddr is still val, FUTEX_WAKE wakes up at most val threads
+pub fn sys_futex(uaddr: *const u32, op: usize, val: usize) -> i64 {
+    sys_call(Syscall::Futex, uaddr as usize, op, val, 0)
+}
+
+// The remaining time is written to rem if it's not null
+pub fn sys_nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> i64 {
+    sys_call(Syscall::Nanosleep, req as usize, rem as usize, 0, 0)
+}
+
+pub fn sys_clock_gettime(clock: usize, tp: *mut TimeSpec) -> i64 {
+    sys_call(Syscall::ClockGettime, clock, tp as usize, 0, 0)
+}
+
+pub fn sys_gettimeofday(tv: *mut TimeVal) -> i64 {
+    sys_call(Syscall::Gettimeofday, tv as usize, 0, 0, 0)
+}
+
+// Larger for more time (used by the stride scheduler), the default is 16
+pub fn sys_set_priority(priority: usize) -> i64 {
+    sys_call(Syscall::SetPriority, priority, 0, 0, 0)
+}
+
+// Wait for the child (any child if pid = -1) to exit, return its tid
+pub fn sys_wait4(pid: isize, status: *mut i32) -> i64 {
+    sys_call(Syscall::Wait4, pid as usize, status as usize, 0, 0)
+}
+
+// Exit code of the child from the status of sys_wait4
+pub fn exit_status(status: i32) -> usize {
+    ((status >> 8) & 0xff) as usize
+}
+
+// Return the new program break, or the current one if it can't be moved
+pub fn sys_brk(addr: usize) -> i64 {
+    sys_call(Syscall::Brk, addr, 0, 0, 0)
+}
+
+// Return the start of the mapping, fd is ignored with MAP_ANONYMOUS
+pub fn sys_mmap(
+    addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> i64 {
+    sys_call6(Syscall::Mmap, [addr, len, prot, flags, fd, offset])
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> i64 {
+    sys_call(Syscall::Munmap, addr, len, 0, 0)
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> i64 {
+    sys_call(Syscall::Mprotect, addr, len, prot, 0)
 }
@@ -0,0 +1,34 @@
+use crate::syscall::{sys_clock_gettime, sys_nanosleep};
+
+// Clocks of sys_clock_gettime
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimeVal {
+    pub sec: usize,
+    pub usec: usize,
+}
+
+pub fn sleep_ms(ms: usize) {
+    let req = TimeSpec {
+        sec: ms / 1000,
+        nsec: ms % 1000 * 1_000_000,
+    };
+    sys_nanosleep(&req, core::ptr::null_mut());
+}
+
+// Milliseconds since boot
+pub fn uptime_ms() -> usize {
+    let mut tp = TimeSpec::default();
+    sys_clock_gettime(CLOCK_MONOTONIC, &mut tp);
+    tp.sec * 1000 + tp.nsec / 1_000_000
+}